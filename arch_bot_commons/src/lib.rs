@@ -8,6 +8,10 @@ use teloxide::{
     types::{Chat, MessageEntity, User},
 };
 
+pub mod media;
+#[cfg(test)]
+mod test_utils;
+pub mod text;
 pub mod useful_methods;
 pub mod user_resolving;
 
@@ -94,7 +98,8 @@ pub fn print_user(user: &User) -> (String, Option<MessageEntity>) {
                 None => first_name,
                 Some(last_name) => first_name + " " + last_name,
             };
-            let len = full_name.len();
+            // the length in MessageEntity is a count of UTF-16 code units
+            let len = text::utf16_len(&full_name);
             (
                 full_name,
                 Some(MessageEntity::text_mention(user.to_owned(), 0, len)),
@@ -145,10 +150,10 @@ pub fn append_with_message_entities(
     (text, entities): (&mut String, &mut Vec<MessageEntity>),
     (text_to_append, entities_to_append): (&str, &Vec<MessageEntity>),
 ) {
-    text.push_str(text_to_append);
-
     // the offset in MessageEntity is a count of UTF-16 code units
-    let additional_offset = text.encode_utf16().count();
+    let additional_offset = text::utf16_len(text);
+
+    text.push_str(text_to_append);
 
     entities.reserve(entities_to_append.len());
     for e in entities_to_append {
@@ -158,6 +163,48 @@ pub fn append_with_message_entities(
     }
 }
 
+/// Turn an error into a short message that can be shown to the user,
+/// instead of the raw error. If `include_raw` is set, the raw error is
+/// added after it, which is handy in debug builds:
+///
+/// ```ignore
+/// let text = friendly_error(&e, cfg!(debug_assertions));
+/// ```
+#[must_use]
+pub fn friendly_error(err: &teloxide::RequestError, include_raw: bool) -> String {
+    use teloxide::{ApiError, RequestError};
+
+    let friendly = match err {
+        RequestError::Api(
+            ApiError::WrongFileId | ApiError::FileIdInvalid | ApiError::WrongFileIdOrUrl,
+        ) => "Couldn't find that file. Try sending it again.".to_string(),
+        RequestError::Api(ApiError::Unknown(text)) if text.contains("file is too big") => {
+            "That file is too big for me to fetch.".to_string()
+        }
+        RequestError::Api(ApiError::MessageIsTooLong | ApiError::EditedMessageIsTooLong) => {
+            "The message came out too long to send.".to_string()
+        }
+        RequestError::Api(ApiError::RequestEntityTooLarge) => {
+            "The file came out too big to send.".to_string()
+        }
+        RequestError::RetryAfter(duration) => format!(
+            "I'm sending too much right now. Try again in {} seconds.",
+            duration.as_secs().max(1)
+        ),
+        RequestError::Network(_) => "Couldn't reach Telegram. Try again in a bit.".to_string(),
+        RequestError::Io(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            "Couldn't find that file. Try sending it again.".to_string()
+        }
+        _ => "Something went wrong, sorry.".to_string(),
+    };
+
+    if include_raw {
+        format!("{friendly}\n\n{err}")
+    } else {
+        friendly
+    }
+}
+
 /// Run a function that returns a `Result<_, RequestError>`, and in case of a
 /// network error, retry it up to 5 times.
 #[macro_export]
@@ -186,3 +233,78 @@ macro_rules! teloxide_retry {
         }
     }};
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use teloxide::{ApiError, RequestError};
+
+    use super::*;
+
+    #[test]
+    fn mentions_are_as_long_as_the_name() {
+        let user: User = serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "is_bot": false,
+            "first_name": "Zoë",
+            "last_name": "🦀",
+        }))
+        .unwrap();
+        let (name, entity) = print_user(&user);
+        assert_eq!(name, "Zoë 🦀");
+        assert_eq!(entity.unwrap().length, 6);
+    }
+
+    #[test]
+    fn appended_entities_start_after_the_text() {
+        let mut text = String::from("héllo 👋 ");
+        let mut entities = vec![MessageEntity::bold(0, 5)];
+        append_with_message_entities(
+            (&mut text, &mut entities),
+            ("world", &vec![MessageEntity::italic(0, 5)]),
+        );
+        assert_eq!(text, "héllo 👋 world");
+        assert_eq!(
+            entities,
+            [MessageEntity::bold(0, 5), MessageEntity::italic(9, 5)]
+        );
+    }
+
+    #[test]
+    fn friendly_errors() {
+        let wrong_file = RequestError::Api(ApiError::WrongFileId);
+        assert_eq!(
+            friendly_error(&wrong_file, false),
+            "Couldn't find that file. Try sending it again."
+        );
+
+        let too_big = RequestError::Api(ApiError::Unknown("Bad Request: file is too big".into()));
+        assert_eq!(
+            friendly_error(&too_big, false),
+            "That file is too big for me to fetch."
+        );
+
+        let retry = RequestError::RetryAfter(Duration::from_secs(5));
+        assert_eq!(
+            friendly_error(&retry, false),
+            "I'm sending too much right now. Try again in 5 seconds."
+        );
+
+        let too_long = RequestError::Api(ApiError::MessageIsTooLong);
+        assert_eq!(
+            friendly_error(&too_long, false),
+            "The message came out too long to send."
+        );
+
+        let other = RequestError::Api(ApiError::BotBlocked);
+        assert_eq!(
+            friendly_error(&other, false),
+            "Something went wrong, sorry."
+        );
+        assert_eq!(
+            friendly_error(&other, true),
+            "Something went wrong, sorry.\n\nA Telegram's error: Forbidden: bot was blocked by the user"
+        );
+    }
+}
@@ -0,0 +1,819 @@
+//! Helpers for working with downloaded media files.
+
+use std::{
+    ffi::OsStr,
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+    time::Duration,
+};
+
+/// Find out the real dimensions of an image from its header, without decoding it.
+/// Useful since dimensions reported by Telegram can be missing or wrong,
+/// like for images sent as documents.
+///
+/// Supports JPEG, PNG, GIF and WebP. Returns `None` for other formats,
+/// or if the header is cut off or broken.
+#[must_use]
+pub fn image_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        png_dimensions(bytes)
+    } else if bytes.starts_with(b"\xff\xd8") {
+        jpeg_dimensions(bytes)
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        Some((
+            u16_le(bytes.get(6..8)?).into(),
+            u16_le(bytes.get(8..10)?).into(),
+        ))
+    } else if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP") {
+        webp_dimensions(bytes)
+    } else {
+        None
+    }
+}
+
+fn u16_le(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+fn u16_be(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+fn u24_le(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0])
+}
+
+fn png_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    // The first chunk is always IHDR, starting with the width and height.
+    if bytes.get(12..16)? != b"IHDR" {
+        return None;
+    }
+    let width = u32::from_be_bytes(bytes.get(16..20)?.try_into().ok()?);
+    let height = u32::from_be_bytes(bytes.get(20..24)?.try_into().ok()?);
+    Some((width, height))
+}
+
+fn jpeg_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let mut pos = 2;
+    loop {
+        if *bytes.get(pos)? != 0xff {
+            return None;
+        }
+        let marker = *bytes.get(pos + 1)?;
+        pos += 2;
+        match marker {
+            // Padding before a marker.
+            0xff => pos -= 1,
+            // Markers without a segment.
+            0x01 | 0xd0..=0xd7 => (),
+            // Start of frame, except for DHT, JPG and DAC, which share the range.
+            0xc0..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => {
+                let segment = bytes.get(pos + 2..pos + 7)?;
+                let height = u16_be(&segment[1..3]);
+                let width = u16_be(&segment[3..5]);
+                return Some((width.into(), height.into()));
+            }
+            _ => pos += usize::from(u16_be(bytes.get(pos..pos + 2)?)),
+        }
+    }
+}
+
+fn webp_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let chunk = bytes.get(20..30)?;
+    match bytes.get(12..16)? {
+        // Lossy, with a VP8 key frame header.
+        b"VP8 " => {
+            if chunk[3..6] != [0x9d, 0x01, 0x2a] {
+                return None;
+            }
+            let width = u16_le(&chunk[6..8]) & 0x3fff;
+            let height = u16_le(&chunk[8..10]) & 0x3fff;
+            Some((width.into(), height.into()))
+        }
+        // Lossless, with 14 bits for each dimension minus one.
+        b"VP8L" => {
+            if chunk[0] != 0x2f {
+                return None;
+            }
+            let bits = u32::from_le_bytes(chunk[1..5].try_into().ok()?);
+            Some(((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1))
+        }
+        // Extended, with 24 bits for each canvas dimension minus one.
+        b"VP8X" => Some((u24_le(&chunk[4..7]) + 1, u24_le(&chunk[7..10]) + 1)),
+        _ => None,
+    }
+}
+
+/// How many bytes from the start of a file [`sniff_content_type`] looks at.
+/// Downloading just that much with [`BotStuff::download_file_head`]
+/// is enough to find out what the file is.
+///
+/// [`BotStuff::download_file_head`]: crate::useful_methods::BotStuff::download_file_head
+pub const SNIFF_LEN: usize = 64;
+
+/// Find out the MIME type of a file by the magic bytes at its start,
+/// since the declared one can be wrong, especially for documents.
+///
+/// Supports JPEG, PNG, GIF, WebP, MP4, WebM, OGG and PDF. Returns `None`
+/// for other formats, or if there aren't enough bytes to tell.
+#[must_use]
+pub fn sniff_content_type(bytes: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"OggS", "audio/ogg"),
+        (b"%PDF-", "application/pdf"),
+    ];
+    if let Some((_, mime)) = SIGNATURES.iter().find(|(x, _)| bytes.starts_with(x)) {
+        return Some(mime);
+    }
+
+    if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP") {
+        return Some("image/webp");
+    }
+    // ISO base media files start with a box of the file's type.
+    if bytes.get(4..8) == Some(b"ftyp") {
+        return Some("video/mp4");
+    }
+    // WebM is Matroska that says it's WebM in its EBML header.
+    if bytes.starts_with(b"\x1a\x45\xdf\xa3") {
+        let header = &bytes[..bytes.len().min(SNIFF_LEN)];
+        if header.windows(4).any(|x| x == b"webm") {
+            return Some("video/webm");
+        }
+    }
+    None
+}
+
+/// Error returned when converting media fails.
+#[derive(Debug)]
+pub enum ConvertError {
+    /// The ffmpeg binary at this path couldn't be found.
+    FfmpegNotFound(PathBuf),
+    /// ffmpeg failed, with this output on stderr.
+    Ffmpeg(String),
+    /// Reading or writing a temporary file failed.
+    Io(std::io::Error),
+    /// The input isn't an image in a format that's understood.
+    UnknownImageFormat,
+}
+
+impl From<std::io::Error> for ConvertError {
+    fn from(e: std::io::Error) -> Self {
+        ConvertError::Io(e)
+    }
+}
+
+impl std::fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConvertError::FfmpegNotFound(path) => {
+                write!(f, "ffmpeg wasn't found at {}", path.display())
+            }
+            ConvertError::Ffmpeg(stderr) => write!(f, "ffmpeg failed: {stderr}"),
+            ConvertError::Io(e) => write!(f, "{e}"),
+            ConvertError::UnknownImageFormat => {
+                write!(f, "The input isn't a PNG, JPEG, GIF or WebP image")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConvertError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConvertError::Io(e) => Some(e),
+            ConvertError::FfmpegNotFound(_)
+            | ConvertError::Ffmpeg(_)
+            | ConvertError::UnknownImageFormat => None,
+        }
+    }
+}
+
+/// Telegram doesn't accept thumbnails bigger than this.
+pub const THUMBNAIL_MAX_BYTES: usize = 200 * 1024;
+
+/// Converts media by running ffmpeg.
+///
+/// All of the conversions block until ffmpeg is done, so in async code
+/// they should be run with [`tokio::task::spawn_blocking`].
+#[derive(Clone, Debug)]
+pub struct Ffmpeg {
+    path: PathBuf,
+}
+
+impl Default for Ffmpeg {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ffmpeg {
+    /// Use the `ffmpeg` binary found in `PATH`.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::with_path("ffmpeg")
+    }
+
+    /// Use the ffmpeg binary at this path.
+    #[must_use]
+    pub fn with_path(path: impl Into<PathBuf>) -> Self {
+        Ffmpeg { path: path.into() }
+    }
+
+    /// Runs ffmpeg with these arguments, returning what it wrote to stdout.
+    fn run<I, S>(&self, args: I) -> Result<Vec<u8>, ConvertError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let output = Command::new(&self.path)
+            .args(["-hide_banner", "-loglevel", "error", "-nostdin", "-y"])
+            .args(args)
+            .output()
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => ConvertError::FfmpegNotFound(self.path.clone()),
+                _ => ConvertError::Io(e),
+            })?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            return Err(ConvertError::Ffmpeg(stderr));
+        }
+        if output.stdout.is_empty() {
+            return Err(ConvertError::Ffmpeg("Nothing was output".to_string()));
+        }
+        Ok(output.stdout)
+    }
+
+    /// Convert a static sticker from WebP to PNG, keeping its transparency.
+    ///
+    /// Only [`StickerKind::Static`] stickers are WebP images, so check
+    /// [`MessageMediaInfo::sticker_kind`] before downloading one for this.
+    ///
+    /// # Errors
+    /// Errors if ffmpeg can't be run, or fails to convert the sticker.
+    ///
+    /// [`StickerKind::Static`]: crate::useful_methods::StickerKind::Static
+    /// [`MessageMediaInfo::sticker_kind`]: crate::useful_methods::MessageMediaInfo::sticker_kind
+    pub fn sticker_webp_to_png(&self, bytes: &[u8]) -> Result<Vec<u8>, ConvertError> {
+        let mut input = tempfile::NamedTempFile::new()?;
+        input.write_all(bytes)?;
+        input.flush()?;
+
+        self.run([
+            OsStr::new("-i"),
+            input.path().as_os_str(),
+            OsStr::new("-frames:v"),
+            OsStr::new("1"),
+            OsStr::new("-c:v"),
+            OsStr::new("png"),
+            OsStr::new("-pix_fmt"),
+            OsStr::new("rgba"),
+            OsStr::new("-f"),
+            OsStr::new("image2pipe"),
+            OsStr::new("-"),
+        ])
+    }
+
+    /// Grab a single frame of a video at this timestamp, as a JPEG image.
+    /// Goes well with [`BotStuff::download_file_to_temp_or_directly`],
+    /// which gives a path to the downloaded video.
+    ///
+    /// # Errors
+    /// Errors if ffmpeg can't be run, fails to read the video,
+    /// or if the video is shorter than the timestamp.
+    ///
+    /// [`BotStuff::download_file_to_temp_or_directly`]: crate::useful_methods::BotStuff::download_file_to_temp_or_directly
+    pub fn extract_video_frame(
+        &self,
+        input: &Path,
+        timestamp: Duration,
+    ) -> Result<Vec<u8>, ConvertError> {
+        // Seeking before the input is much faster, and accurate when transcoding.
+        let timestamp = format!("{:.3}", timestamp.as_secs_f64());
+        self.run([
+            OsStr::new("-ss"),
+            OsStr::new(&timestamp),
+            OsStr::new("-i"),
+            input.as_os_str(),
+            OsStr::new("-frames:v"),
+            OsStr::new("1"),
+            OsStr::new("-c:v"),
+            OsStr::new("mjpeg"),
+            OsStr::new("-f"),
+            OsStr::new("image2pipe"),
+            OsStr::new("-"),
+        ])
+    }
+
+    /// Make a JPEG thumbnail for a video, to send along with it. The frame
+    /// is taken a second in, to skip black frames at the start, or from the
+    /// start if the video is shorter. It's scaled down to fit in `max_side`
+    /// pixels on both sides, and compressed more until it's under
+    /// [`THUMBNAIL_MAX_BYTES`]. Telegram wants thumbnails to be at most 320
+    /// pixels on each side.
+    ///
+    /// If even the strongest compression doesn't make it small enough,
+    /// the thumbnail is returned as it is.
+    ///
+    /// # Errors
+    /// Errors if ffmpeg can't be run, or fails to read the video.
+    pub fn generate_thumbnail(
+        &self,
+        video_path: &Path,
+        max_side: u32,
+    ) -> Result<Vec<u8>, ConvertError> {
+        // Commas are escaped, since otherwise they would separate filters.
+        let scale = format!(
+            "scale=w=min(iw\\,{max_side}):h=min(ih\\,{max_side}):force_original_aspect_ratio=decrease"
+        );
+        let run = |timestamp: &str, quality: &str| {
+            self.run([
+                OsStr::new("-ss"),
+                OsStr::new(timestamp),
+                OsStr::new("-i"),
+                video_path.as_os_str(),
+                OsStr::new("-frames:v"),
+                OsStr::new("1"),
+                OsStr::new("-vf"),
+                OsStr::new(&scale),
+                OsStr::new("-c:v"),
+                OsStr::new("mjpeg"),
+                OsStr::new("-q:v"),
+                OsStr::new(quality),
+                OsStr::new("-f"),
+                OsStr::new("image2pipe"),
+                OsStr::new("-"),
+            ])
+        };
+
+        let mut timestamp = "1";
+        let mut thumbnail = Vec::new();
+        // Lower is better quality, 31 is the worst.
+        for quality in ["2", "5", "10", "20", "31"] {
+            thumbnail = match run(timestamp, quality) {
+                // Seeking past the end outputs nothing, which is an error,
+                // so short videos get a frame from the start instead.
+                Err(ConvertError::Ffmpeg(_)) if timestamp == "1" => {
+                    timestamp = "0";
+                    run(timestamp, quality)?
+                }
+                result => result?,
+            };
+            if thumbnail.len() <= THUMBNAIL_MAX_BYTES {
+                break;
+            }
+        }
+        Ok(thumbnail)
+    }
+
+    /// Convert a video, like a Telegram GIF (which is actually an MP4 video)
+    /// or a video sticker, into a real GIF. Generates a palette from the
+    /// video first, so colors look decent.
+    ///
+    /// This is slow, so only do it for media that
+    /// [`MessageMediaInfo::can_convert_to_gif`].
+    ///
+    /// # Errors
+    /// Errors if ffmpeg can't be run, or fails to convert the video.
+    ///
+    /// [`MessageMediaInfo::can_convert_to_gif`]: crate::useful_methods::MessageMediaInfo::can_convert_to_gif
+    pub fn to_gif(&self, input: &Path) -> Result<Vec<u8>, ConvertError> {
+        self.run([
+            OsStr::new("-i"),
+            input.as_os_str(),
+            OsStr::new("-filter_complex"),
+            OsStr::new(
+                "split[a][b];[a]palettegen=reserve_transparent=1[palette];[b][palette]paletteuse",
+            ),
+            OsStr::new("-f"),
+            OsStr::new("gif"),
+            OsStr::new("-"),
+        ])
+    }
+
+    /// Resize an image to fit Telegram's requirements for static stickers,
+    /// see [`sticker_dimensions`], and convert it to PNG, keeping its
+    /// transparency.
+    ///
+    /// # Errors
+    /// Errors with [`ConvertError::UnknownImageFormat`] if the input isn't
+    /// a PNG, JPEG, GIF or WebP image, or if ffmpeg can't be run,
+    /// or fails to convert the image.
+    pub fn resize_for_sticker(&self, bytes: &[u8]) -> Result<Vec<u8>, ConvertError> {
+        let (width, height) = image_dimensions(bytes).ok_or(ConvertError::UnknownImageFormat)?;
+        let (width, height) = sticker_dimensions(width, height);
+
+        let mut input = tempfile::NamedTempFile::new()?;
+        input.write_all(bytes)?;
+        input.flush()?;
+
+        let scale = format!("scale={width}:{height}:flags=lanczos");
+        self.run([
+            OsStr::new("-i"),
+            input.path().as_os_str(),
+            OsStr::new("-frames:v"),
+            OsStr::new("1"),
+            OsStr::new("-vf"),
+            OsStr::new(&scale),
+            OsStr::new("-c:v"),
+            OsStr::new("png"),
+            OsStr::new("-pix_fmt"),
+            OsStr::new("rgba"),
+            OsStr::new("-f"),
+            OsStr::new("image2pipe"),
+            OsStr::new("-"),
+        ])
+    }
+
+    /// Perceptual hash of an image, which stays about the same when the
+    /// image is resized or recompressed, unlike a hash of its bytes.
+    /// Compare two of them with [`hamming_distance`].
+    ///
+    /// This is a difference hash: the image is shrunk to 9x8 grayscale
+    /// pixels, and each bit tells if a pixel is darker than the next one.
+    ///
+    /// # Errors
+    /// Errors if ffmpeg can't be run, or fails to read the image.
+    pub fn phash_image(&self, bytes: &[u8]) -> Result<u64, ConvertError> {
+        let mut input = tempfile::NamedTempFile::new()?;
+        input.write_all(bytes)?;
+        input.flush()?;
+
+        let pixels = self.run([
+            OsStr::new("-i"),
+            input.path().as_os_str(),
+            OsStr::new("-frames:v"),
+            OsStr::new("1"),
+            OsStr::new("-vf"),
+            OsStr::new("scale=9:8:flags=area,format=gray"),
+            OsStr::new("-f"),
+            OsStr::new("rawvideo"),
+            OsStr::new("-"),
+        ])?;
+        let pixels: [u8; 72] = pixels.try_into().map_err(|x: Vec<u8>| {
+            ConvertError::Ffmpeg(format!("Expected 72 pixels, got {}", x.len()))
+        })?;
+        Ok(difference_hash(&pixels))
+    }
+}
+
+/// Size that an image of this size has to be scaled to, to be used as a
+/// static sticker: the longest side is 512 pixels, and the aspect ratio
+/// is kept.
+#[must_use]
+pub fn sticker_dimensions(width: u32, height: u32) -> (u32, u32) {
+    const SIDE: u64 = 512;
+    let (long, short) = (width.max(height).max(1), width.min(height));
+    let scaled = (u64::from(short) * SIDE + u64::from(long) / 2) / u64::from(long);
+    let scaled = scaled.clamp(1, SIDE) as u32;
+    if width >= height {
+        (SIDE as u32, scaled)
+    } else {
+        (scaled, SIDE as u32)
+    }
+}
+
+/// Hashes 9x8 grayscale pixels, see [`Ffmpeg::phash_image`].
+fn difference_hash(pixels: &[u8; 72]) -> u64 {
+    let mut hash = 0;
+    for row in pixels.chunks_exact(9) {
+        for pair in row.windows(2) {
+            hash = (hash << 1) | u64::from(pair[0] < pair[1]);
+        }
+    }
+    hash
+}
+
+/// How many bits are different between two perceptual hashes from
+/// [`phash_image`]. Images with a distance of up to about 10 out of 64
+/// are likely the same picture.
+#[must_use]
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Convert a static sticker from WebP to PNG with ffmpeg from `PATH`.
+/// See [`Ffmpeg::sticker_webp_to_png`].
+///
+/// # Errors
+/// Errors if ffmpeg can't be run, or fails to convert the sticker.
+pub fn sticker_webp_to_png(bytes: &[u8]) -> Result<Vec<u8>, ConvertError> {
+    Ffmpeg::new().sticker_webp_to_png(bytes)
+}
+
+/// Grab a single frame of a video as a JPEG image with ffmpeg from `PATH`.
+/// See [`Ffmpeg::extract_video_frame`].
+///
+/// # Errors
+/// Errors if ffmpeg can't be run, fails to read the video,
+/// or if the video is shorter than the timestamp.
+pub fn extract_video_frame(input: &Path, timestamp: Duration) -> Result<Vec<u8>, ConvertError> {
+    Ffmpeg::new().extract_video_frame(input, timestamp)
+}
+
+/// Convert a video into a GIF with ffmpeg from `PATH`. See [`Ffmpeg::to_gif`].
+///
+/// # Errors
+/// Errors if ffmpeg can't be run, or fails to convert the video.
+pub fn to_gif(input: &Path) -> Result<Vec<u8>, ConvertError> {
+    Ffmpeg::new().to_gif(input)
+}
+
+/// Make a thumbnail for a video with ffmpeg from `PATH`.
+/// See [`Ffmpeg::generate_thumbnail`].
+///
+/// # Errors
+/// Errors if ffmpeg can't be run, or fails to read the video.
+pub fn generate_thumbnail(video_path: &Path, max_side: u32) -> Result<Vec<u8>, ConvertError> {
+    Ffmpeg::new().generate_thumbnail(video_path, max_side)
+}
+
+/// Resize an image for a sticker with ffmpeg from `PATH`.
+/// See [`Ffmpeg::resize_for_sticker`].
+///
+/// # Errors
+/// Errors if the input isn't an image that's understood, or if ffmpeg
+/// can't be run, or fails to convert the image.
+pub fn resize_for_sticker(bytes: &[u8]) -> Result<Vec<u8>, ConvertError> {
+    Ffmpeg::new().resize_for_sticker(bytes)
+}
+
+/// Perceptual hash of an image with ffmpeg from `PATH`.
+/// See [`Ffmpeg::phash_image`].
+///
+/// # Errors
+/// Errors if ffmpeg can't be run, or fails to read the image.
+pub fn phash_image(bytes: &[u8]) -> Result<u64, ConvertError> {
+    Ffmpeg::new().phash_image(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn png_and_gif() {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend(512u32.to_be_bytes());
+        png.extend(300u32.to_be_bytes());
+        png.extend([8, 6, 0, 0, 0]);
+        assert_eq!(image_dimensions(&png), Some((512, 300)));
+        assert_eq!(image_dimensions(&png[..20]), None);
+
+        let mut gif = b"GIF89a".to_vec();
+        gif.extend(100u16.to_le_bytes());
+        gif.extend(50u16.to_le_bytes());
+        assert_eq!(image_dimensions(&gif), Some((100, 50)));
+    }
+
+    #[test]
+    fn jpeg() {
+        let mut jpeg = vec![0xff, 0xd8];
+        // APP0 segment that should be skipped.
+        jpeg.extend([0xff, 0xe0, 0, 6, b'J', b'F', b'I', b'F']);
+        // SOF0 segment.
+        jpeg.extend([0xff, 0xff, 0xc0, 0, 11, 8]);
+        jpeg.extend(480u16.to_be_bytes());
+        jpeg.extend(640u16.to_be_bytes());
+        jpeg.extend([3, 1, 0x22, 0]);
+        assert_eq!(image_dimensions(&jpeg), Some((640, 480)));
+        assert_eq!(image_dimensions(&jpeg[..10]), None);
+    }
+
+    #[test]
+    fn webp() {
+        let riff = |chunk: &[u8], data: &[u8]| {
+            let mut webp = b"RIFF\0\0\0\0WEBP".to_vec();
+            webp.extend(chunk);
+            webp.extend((data.len() as u32).to_le_bytes());
+            webp.extend(data);
+            webp
+        };
+
+        let mut lossy = vec![0, 0, 0, 0x9d, 0x01, 0x2a];
+        lossy.extend(512u16.to_le_bytes());
+        lossy.extend(256u16.to_le_bytes());
+        assert_eq!(image_dimensions(&riff(b"VP8 ", &lossy)), Some((512, 256)));
+
+        let bits: u32 = (512 - 1) | ((512 - 1) << 14);
+        let mut lossless = vec![0x2f];
+        lossless.extend(bits.to_le_bytes());
+        lossless.extend([0; 5]);
+        assert_eq!(
+            image_dimensions(&riff(b"VP8L", &lossless)),
+            Some((512, 512))
+        );
+
+        let mut extended = vec![0x10, 0, 0, 0];
+        extended.extend(&(1000u32 - 1).to_le_bytes()[..3]);
+        extended.extend(&(2u32 - 1).to_le_bytes()[..3]);
+        assert_eq!(image_dimensions(&riff(b"VP8X", &extended)), Some((1000, 2)));
+
+        assert_eq!(image_dimensions(b"not an image at all"), None);
+    }
+
+    #[test]
+    fn sniffing() {
+        let cases: &[(&[u8], Option<&str>)] = &[
+            (b"\xff\xd8\xff\xe0\0\x10JFIF", Some("image/jpeg")),
+            (b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR", Some("image/png")),
+            (b"GIF89a\x01\0\x01\0", Some("image/gif")),
+            (b"GIF87a\x01\0\x01\0", Some("image/gif")),
+            (b"RIFF\x1a\0\0\0WEBPVP8L", Some("image/webp")),
+            (b"\0\0\0\x20ftypisom\0\0\x02\0", Some("video/mp4")),
+            (
+                b"\x1a\x45\xdf\xa3\x9f\x42\x86\x81\x01\x42\x82\x84webm",
+                Some("video/webm"),
+            ),
+            (b"\x1a\x45\xdf\xa3\xa3\x42\x82\x88matroska", None),
+            (b"OggS\0\x02\0\0", Some("audio/ogg")),
+            (b"%PDF-1.7\n", Some("application/pdf")),
+            (b"RIFF\x1a\0\0\0WAVEfmt ", None),
+            (b"just some text", None),
+            (b"\xff\xd8", None),
+            (b"", None),
+        ];
+        for (bytes, expected) in cases {
+            assert_eq!(sniff_content_type(bytes), *expected, "for {bytes:?}");
+        }
+    }
+
+    #[test]
+    fn missing_ffmpeg_is_reported() {
+        let ffmpeg = Ffmpeg::with_path("/nonexistent/ffmpeg");
+        assert!(matches!(
+            ffmpeg.sticker_webp_to_png(b"whatever"),
+            Err(ConvertError::FfmpegNotFound(path)) if path == Path::new("/nonexistent/ffmpeg")
+        ));
+        assert!(matches!(
+            ffmpeg.extract_video_frame(Path::new("video.mp4"), Duration::ZERO),
+            Err(ConvertError::FfmpegNotFound(_))
+        ));
+        assert!(matches!(
+            ffmpeg.to_gif(Path::new("video.mp4")),
+            Err(ConvertError::FfmpegNotFound(_))
+        ));
+        assert!(matches!(
+            ffmpeg.phash_image(b"whatever"),
+            Err(ConvertError::FfmpegNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn difference_hashes() {
+        // Every row gets brighter to the right.
+        let mut gradient = [0; 72];
+        for (i, pixel) in gradient.iter_mut().enumerate() {
+            *pixel = (i % 9) as u8 * 20;
+        }
+        assert_eq!(difference_hash(&gradient), u64::MAX);
+        assert_eq!(difference_hash(&[128; 72]), 0);
+
+        // A single brighter pixel in the middle of the first row.
+        let mut spot = [128; 72];
+        spot[4] = 200;
+        assert_eq!(difference_hash(&spot), 1 << 60);
+
+        assert_eq!(hamming_distance(u64::MAX, 0), 64);
+        assert_eq!(hamming_distance(0b1011, 0b0010), 2);
+        assert_eq!(hamming_distance(42, 42), 0);
+    }
+
+    #[test]
+    fn sticker_sizes() {
+        assert_eq!(sticker_dimensions(1024, 768), (512, 384));
+        assert_eq!(sticker_dimensions(100, 200), (256, 512));
+        assert_eq!(sticker_dimensions(512, 512), (512, 512));
+        assert_eq!(sticker_dimensions(64, 48), (512, 384));
+        assert_eq!(sticker_dimensions(10_000, 1), (512, 1));
+        assert_eq!(sticker_dimensions(0, 0), (512, 1));
+    }
+
+    #[test]
+    fn sticker_from_unknown_format() {
+        // Rejected before ffmpeg is even run.
+        let result = Ffmpeg::with_path("/no/ffmpeg/here").resize_for_sticker(b"not an image");
+        assert!(matches!(result, Err(ConvertError::UnknownImageFormat)));
+    }
+
+    #[test]
+    #[ignore = "needs ffmpeg"]
+    fn image_resized_for_sticker() {
+        let video = test_video(Duration::from_secs(2));
+        let frame = extract_video_frame(video.path(), Duration::from_secs(1)).unwrap();
+        let sticker = resize_for_sticker(&frame).unwrap();
+        assert!(sticker.starts_with(b"\x89PNG"));
+        assert_eq!(image_dimensions(&sticker), Some((512, 384)));
+        assert_eq!(sticker[25], 6);
+    }
+
+    #[test]
+    #[ignore = "needs ffmpeg"]
+    fn transparent_webp_to_png() {
+        // Lossless 1x1 WebP of a transparent pixel.
+        let webp = [
+            b'R', b'I', b'F', b'F', 0x1a, 0, 0, 0, b'W', b'E', b'B', b'P', b'V', b'P', b'8', b'L',
+            0x0d, 0, 0, 0, 0x2f, 0, 0, 0, 0x10, 0x07, 0x10, 0x11, 0x11, 0x88, 0x88, 0xfe, 0x07, 0,
+        ];
+        assert_eq!(image_dimensions(&webp), Some((1, 1)));
+
+        let png = sticker_webp_to_png(&webp).unwrap();
+        assert_eq!(image_dimensions(&png), Some((1, 1)));
+        // Color type in IHDR is RGBA, so the alpha channel survived.
+        assert_eq!(png[25], 6);
+    }
+
+    /// Makes a test pattern video of this length with ffmpeg.
+    fn test_video(duration: Duration) -> tempfile::NamedTempFile {
+        let video = tempfile::Builder::new().suffix(".mp4").tempfile().unwrap();
+        let source = format!(
+            "testsrc=duration={}:size=64x48:rate=10",
+            duration.as_secs_f64()
+        );
+        let status = Command::new("ffmpeg")
+            .args(["-loglevel", "error", "-y", "-f", "lavfi", "-i", &source])
+            .arg(video.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        video
+    }
+
+    #[test]
+    #[ignore = "needs ffmpeg"]
+    fn video_frame_is_jpeg() {
+        let video = test_video(Duration::from_secs(2));
+        let frame = extract_video_frame(video.path(), Duration::from_secs(1)).unwrap();
+        assert!(frame.starts_with(b"\xff\xd8"));
+        assert_eq!(image_dimensions(&frame), Some((64, 48)));
+
+        assert!(matches!(
+            extract_video_frame(video.path(), Duration::from_secs(10)),
+            Err(ConvertError::Ffmpeg(_))
+        ));
+    }
+
+    #[test]
+    #[ignore = "needs ffmpeg"]
+    fn video_thumbnails() {
+        let video = test_video(Duration::from_secs(3));
+        let thumbnail = generate_thumbnail(video.path(), 32).unwrap();
+        assert!(thumbnail.starts_with(b"\xff\xd8"));
+        assert!(thumbnail.len() <= THUMBNAIL_MAX_BYTES);
+        assert_eq!(image_dimensions(&thumbnail), Some((32, 24)));
+
+        // Small videos aren't scaled up, and short ones still get one.
+        let video = test_video(Duration::from_millis(500));
+        let thumbnail = generate_thumbnail(video.path(), 320).unwrap();
+        assert_eq!(image_dimensions(&thumbnail), Some((64, 48)));
+    }
+
+    #[test]
+    #[ignore = "needs ffmpeg"]
+    fn video_to_gif() {
+        let video = test_video(Duration::from_secs(1));
+        let gif = to_gif(video.path()).unwrap();
+        assert!(gif.starts_with(b"GIF89a"));
+        assert_eq!(image_dimensions(&gif), Some((64, 48)));
+    }
+
+    #[test]
+    #[ignore = "needs ffmpeg"]
+    fn recompressed_image_hashes_close() {
+        let video = test_video(Duration::from_secs(2));
+        let frame = extract_video_frame(video.path(), Duration::from_secs(1)).unwrap();
+        let other_image = Command::new("ffmpeg")
+            .args([
+                "-loglevel",
+                "error",
+                "-f",
+                "lavfi",
+                "-i",
+                "smptebars=size=64x48",
+            ])
+            .args(["-frames:v", "1", "-f", "image2pipe", "-c:v", "mjpeg", "-"])
+            .output()
+            .unwrap()
+            .stdout;
+
+        let original = tempfile::Builder::new().suffix(".jpg").tempfile().unwrap();
+        std::fs::write(original.path(), &frame).unwrap();
+        let recompressed = Command::new("ffmpeg")
+            .args(["-loglevel", "error", "-i"])
+            .arg(original.path())
+            .args(["-q:v", "25", "-f", "image2pipe", "-c:v", "mjpeg", "-"])
+            .output()
+            .unwrap()
+            .stdout;
+        assert_ne!(recompressed, frame);
+
+        let hash = phash_image(&frame).unwrap();
+        assert!(hamming_distance(hash, phash_image(&recompressed).unwrap()) <= 6);
+        assert!(hamming_distance(hash, phash_image(&other_image).unwrap()) > 6);
+    }
+}
@@ -0,0 +1,182 @@
+//! Helpers for tests that need to talk to Telegram.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use teloxide::Bot;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// A request received by [`MockServer`].
+#[derive(Clone, Debug)]
+pub struct MockRequest {
+    /// Name of the Bot API method, like `SendMessage`.
+    pub method: String,
+    pub body: String,
+}
+
+type Respond = dyn Fn(&MockRequest) -> serde_json::Value + Send + Sync;
+
+/// A fake Bot API server on localhost, which answers requests with
+/// whatever `respond` returns, and remembers them.
+pub struct MockServer {
+    pub bot: Bot,
+    requests: Arc<Mutex<Vec<MockRequest>>>,
+}
+
+impl MockServer {
+    pub async fn new(
+        respond: impl Fn(&MockRequest) -> serde_json::Value + Send + Sync + 'static,
+    ) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let respond: Arc<Respond> = Arc::new(respond);
+
+        let requests_clone = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let requests = requests_clone.clone();
+                let respond = respond.clone();
+                tokio::spawn(handle(stream, requests, respond));
+            }
+        });
+
+        let client = teloxide::net::default_reqwest_settings()
+            .no_proxy()
+            .build()
+            .unwrap();
+        let bot = Bot::with_client("1234:token", client).set_api_url(url.parse().unwrap());
+        MockServer { bot, requests }
+    }
+
+    /// All requests received so far, in order.
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Names of all methods called so far, in order.
+    pub fn methods(&self) -> Vec<String> {
+        self.requests().into_iter().map(|x| x.method).collect()
+    }
+}
+
+/// A successful Bot API response.
+pub fn ok(result: serde_json::Value) -> serde_json::Value {
+    serde_json::json!({ "ok": true, "result": result })
+}
+
+/// Makes [`MockServer`] cut the response short, as if the connection
+/// dropped halfway through it.
+pub fn dropped_connection() -> serde_json::Value {
+    serde_json::json!({ "mock_server": "dropped connection" })
+}
+
+/// Makes [`MockServer`] send `response` a byte at a time, waiting `delay`
+/// before each, like a very slow connection.
+pub fn slow_response(response: serde_json::Value, delay: Duration) -> serde_json::Value {
+    serde_json::json!({
+        "mock_server": "slow response",
+        "response": response,
+        "delay_ms": delay.as_millis() as u64,
+    })
+}
+
+/// A message in a private chat, as returned by `send*` methods.
+pub fn sent_message(id: i32) -> serde_json::Value {
+    serde_json::json!({
+        "message_id": id,
+        "date": 0,
+        "chat": { "id": 1, "type": "private", "first_name": "user" },
+        "text": "sent",
+    })
+}
+
+async fn handle(
+    mut stream: TcpStream,
+    requests: Arc<Mutex<Vec<MockRequest>>>,
+    respond: Arc<Respond>,
+) {
+    let mut data = Vec::new();
+    let mut buf = [0; 8192];
+
+    // Read the head, then as much of the body as it says there is.
+    let head_end = loop {
+        let Ok(read @ 1..) = stream.read(&mut buf).await else {
+            return;
+        };
+        data.extend_from_slice(&buf[..read]);
+        if let Some(i) = data.windows(4).position(|x| x == b"\r\n\r\n") {
+            break i + 4;
+        }
+    };
+    let head = String::from_utf8_lossy(&data[..head_end]).into_owned();
+    let headers = head.to_lowercase();
+    let chunked = headers.contains("transfer-encoding: chunked");
+    let content_length = headers
+        .lines()
+        .find_map(|x| x.strip_prefix("content-length:"))
+        .and_then(|x| x.trim().parse::<usize>().ok());
+
+    loop {
+        let body = &data[head_end..];
+        let done = match content_length {
+            Some(len) => body.len() >= len,
+            None => !chunked || body.ends_with(b"0\r\n\r\n"),
+        };
+        if done {
+            break;
+        }
+        let Ok(read @ 1..) = stream.read(&mut buf).await else {
+            break;
+        };
+        data.extend_from_slice(&buf[..read]);
+    }
+
+    let path = head.split_whitespace().nth(1).unwrap_or_default();
+    let request = MockRequest {
+        method: path.rsplit('/').next().unwrap_or_default().to_string(),
+        body: String::from_utf8_lossy(&data[head_end..]).into_owned(),
+    };
+
+    let response = respond(&request);
+    requests.lock().unwrap().push(request);
+
+    let mut delay = None;
+    // Promise more than is sent when told to drop the connection.
+    let (response, len) = if response == dropped_connection() {
+        let partial = "partial";
+        (partial.to_string(), partial.len() + 100)
+    } else if response["mock_server"] == "slow response" {
+        delay = response["delay_ms"].as_u64().map(Duration::from_millis);
+        let response = response["response"].to_string();
+        let len = response.len();
+        (response, len)
+    } else {
+        let response = response.to_string();
+        let len = response.len();
+        (response, len)
+    };
+    let head = format!(
+        "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {len}\r\nconnection: close\r\n\r\n"
+    );
+    stream.write_all(head.as_bytes()).await.ok();
+    match delay {
+        Some(delay) => {
+            for byte in response.as_bytes() {
+                tokio::time::sleep(delay).await;
+                if stream.write_all(&[*byte]).await.is_err() {
+                    break;
+                }
+            }
+        }
+        None => {
+            stream.write_all(response.as_bytes()).await.ok();
+        }
+    }
+    stream.shutdown().await.ok();
+}
@@ -0,0 +1,405 @@
+//! Helpers for measuring and formatting text the way Telegram does.
+//!
+//! Entity offsets and length limits of messages and captions are all
+//! counted in UTF-16 code units, not in bytes or `char`s.
+
+use std::cmp::Reverse;
+
+use teloxide::types::{MessageEntity, MessageEntityKind};
+
+/// Length of `text` in UTF-16 code units. Characters outside of the basic
+/// plane, like most emoji, count as two.
+#[must_use]
+pub fn utf16_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+/// Returns the longest start of `text` that's at most `max_units` UTF-16
+/// code units long. Never cuts a character in half, so the result can be
+/// a unit shorter than `max_units`.
+#[must_use]
+pub fn truncate_utf16(text: &str, max_units: usize) -> &str {
+    let mut units = 0;
+    for (i, c) in text.char_indices() {
+        units += c.len_utf16();
+        if units > max_units {
+            return &text[..i];
+        }
+    }
+    text
+}
+
+/// Turn text with entities, like that of a received message, back into
+/// MarkdownV2 that can be sent with [`ParseMode::MarkdownV2`] to get
+/// the same formatting. Everything else is escaped.
+///
+/// Entities that Telegram finds by itself, like mentions and links that
+/// are written out, are left as plain text. Entities inside code are
+/// dropped, since code can't have any.
+///
+/// [`ParseMode::MarkdownV2`]: teloxide::types::ParseMode::MarkdownV2
+#[must_use]
+pub fn entities_to_markdown(text: &str, entities: &[MessageEntity]) -> String {
+    render::<Markdown>(text, entities)
+}
+
+/// Like [`entities_to_markdown`], but makes HTML to be sent with
+/// [`ParseMode::Html`].
+///
+/// [`ParseMode::Html`]: teloxide::types::ParseMode::Html
+#[must_use]
+pub fn entities_to_html(text: &str, entities: &[MessageEntity]) -> String {
+    render::<Html>(text, entities)
+}
+
+/// How formatting is written in one of the parse modes.
+trait Syntax {
+    fn escape(text: &str, in_code: bool, to: &mut String);
+    fn open(kind: &MessageEntityKind, to: &mut String);
+    fn close(kind: &MessageEntityKind, to: &mut String);
+}
+
+struct Markdown;
+
+impl Markdown {
+    /// Appends markup, separating it from the previous one with `\r`,
+    /// which Telegram ignores, if both of them are underscores. Otherwise
+    /// italic next to underline would be read wrong.
+    fn push(to: &mut String, markup: &str) {
+        if to.ends_with('_') && markup.starts_with('_') {
+            to.push('\r');
+        }
+        to.push_str(markup);
+    }
+}
+
+impl Syntax for Markdown {
+    fn escape(text: &str, in_code: bool, to: &mut String) {
+        for c in text.chars() {
+            let special = if in_code {
+                matches!(c, '`' | '\\')
+            } else {
+                "_*[]()~`>#+-=|{}.!\\".contains(c)
+            };
+            if special {
+                to.push('\\');
+            }
+            to.push(c);
+        }
+    }
+    fn open(kind: &MessageEntityKind, to: &mut String) {
+        use MessageEntityKind::*;
+        match kind {
+            Bold => Markdown::push(to, "*"),
+            Italic => Markdown::push(to, "_"),
+            Underline => Markdown::push(to, "__"),
+            Strikethrough => Markdown::push(to, "~"),
+            Spoiler => Markdown::push(to, "||"),
+            Code => Markdown::push(to, "`"),
+            Pre { language } => {
+                Markdown::push(to, "```");
+                to.push_str(language.as_deref().unwrap_or_default());
+                to.push('\n');
+            }
+            TextLink { .. } | TextMention { .. } => Markdown::push(to, "["),
+            CustomEmoji { .. } => Markdown::push(to, "!["),
+            _ => {}
+        }
+    }
+    fn close(kind: &MessageEntityKind, to: &mut String) {
+        use MessageEntityKind::*;
+        let url = match kind {
+            Bold => return Markdown::push(to, "*"),
+            Italic => return Markdown::push(to, "_"),
+            Underline => return Markdown::push(to, "__"),
+            Strikethrough => return Markdown::push(to, "~"),
+            Spoiler => return Markdown::push(to, "||"),
+            Code => return Markdown::push(to, "`"),
+            Pre { .. } => return Markdown::push(to, "```"),
+            TextLink { url } => url.to_string(),
+            TextMention { user } => user.url().to_string(),
+            CustomEmoji { custom_emoji_id } => format!("tg://emoji?id={custom_emoji_id}"),
+            _ => return,
+        };
+        to.push_str("](");
+        for c in url.chars() {
+            if matches!(c, ')' | '\\') {
+                to.push('\\');
+            }
+            to.push(c);
+        }
+        to.push(')');
+    }
+}
+
+struct Html;
+
+impl Syntax for Html {
+    fn escape(text: &str, _in_code: bool, to: &mut String) {
+        for c in text.chars() {
+            match c {
+                '<' => to.push_str("&lt;"),
+                '>' => to.push_str("&gt;"),
+                '&' => to.push_str("&amp;"),
+                '"' => to.push_str("&quot;"),
+                c => to.push(c),
+            }
+        }
+    }
+    fn open(kind: &MessageEntityKind, to: &mut String) {
+        use MessageEntityKind::*;
+        let (tag, attribute) = match kind {
+            Bold => ("<b", None),
+            Italic => ("<i", None),
+            Underline => ("<u", None),
+            Strikethrough => ("<s", None),
+            Spoiler => ("<tg-spoiler", None),
+            Code => ("<code", None),
+            Pre { language: None } => ("<pre", None),
+            Pre {
+                language: Some(language),
+            } => (
+                "<pre><code",
+                Some(("class", format!("language-{language}"))),
+            ),
+            TextLink { url } => ("<a", Some(("href", url.to_string()))),
+            TextMention { user } => ("<a", Some(("href", user.url().to_string()))),
+            CustomEmoji { custom_emoji_id } => {
+                ("<tg-emoji", Some(("emoji-id", custom_emoji_id.clone())))
+            }
+            _ => return,
+        };
+        to.push_str(tag);
+        if let Some((name, value)) = attribute {
+            to.push(' ');
+            to.push_str(name);
+            to.push_str("=\"");
+            Html::escape(&value, false, to);
+            to.push('"');
+        }
+        to.push('>');
+    }
+    fn close(kind: &MessageEntityKind, to: &mut String) {
+        use MessageEntityKind::*;
+        to.push_str(match kind {
+            Bold => "</b>",
+            Italic => "</i>",
+            Underline => "</u>",
+            Strikethrough => "</s>",
+            Spoiler => "</tg-spoiler>",
+            Code => "</code>",
+            Pre { language: None } => "</pre>",
+            Pre { language: Some(_) } => "</code></pre>",
+            TextLink { .. } | TextMention { .. } => "</a>",
+            CustomEmoji { .. } => "</tg-emoji>",
+            _ => "",
+        });
+    }
+}
+
+/// Whether the entity is formatting that has to be written out,
+/// as opposed to something Telegram finds in the text by itself.
+fn is_formatting(kind: &MessageEntityKind) -> bool {
+    use MessageEntityKind::*;
+    !matches!(
+        kind,
+        Mention | Hashtag | Cashtag | BotCommand | Url | Email | PhoneNumber
+    )
+}
+
+fn is_code(kind: &MessageEntityKind) -> bool {
+    matches!(
+        kind,
+        MessageEntityKind::Code | MessageEntityKind::Pre { .. }
+    )
+}
+
+/// Byte index in `text` of the character at `units` UTF-16 code units in,
+/// or the end of `text` if it's shorter than that.
+fn byte_offset(text: &str, units: usize) -> usize {
+    let mut count = 0;
+    for (i, c) in text.char_indices() {
+        if count >= units {
+            return i;
+        }
+        count += c.len_utf16();
+    }
+    text.len()
+}
+
+fn render<S: Syntax>(text: &str, entities: &[MessageEntity]) -> String {
+    // Byte ranges of the entities, with the ones that start first and end
+    // last first, so that entities nested inside of others come after them.
+    let mut spans: Vec<_> = entities
+        .iter()
+        .filter(|x| x.length > 0 && is_formatting(&x.kind))
+        .map(|x| {
+            let start = byte_offset(text, x.offset);
+            let end = byte_offset(text, x.offset + x.length);
+            (start, end, &x.kind)
+        })
+        .filter(|(start, end, _)| start < end)
+        .collect();
+    spans.sort_by_key(|&(start, end, _)| (start, Reverse(end)));
+
+    let mut boundaries: Vec<_> = spans
+        .iter()
+        .flat_map(|&(start, end, _)| [start, end])
+        .collect();
+    boundaries.push(text.len());
+    boundaries.sort_unstable();
+    boundaries.dedup();
+
+    let mut output = String::with_capacity(text.len() * 2);
+    // Entities that are open right now, outermost first.
+    let mut open: Vec<(usize, &MessageEntityKind)> = Vec::new();
+    let mut next_span = spans.iter().peekable();
+    let mut position = 0;
+    for boundary in boundaries {
+        let in_code = open.iter().any(|(_, kind)| is_code(kind));
+        S::escape(&text[position..boundary], in_code, &mut output);
+        position = boundary;
+
+        // Entities can overlap without one being inside of the other, so to
+        // close one, close everything opened after it, and open those again.
+        if let Some(first_ended) = open.iter().position(|&(end, _)| end <= boundary) {
+            let closed = open.split_off(first_ended);
+            for (_, kind) in closed.iter().rev() {
+                S::close(kind, &mut output);
+            }
+            for &(end, kind) in closed.iter().filter(|(end, _)| *end > boundary) {
+                S::open(kind, &mut output);
+                open.push((end, kind));
+            }
+        }
+
+        while let Some(&(_, end, kind)) = next_span.next_if(|(start, _, _)| *start == boundary) {
+            if open.iter().any(|(_, kind)| is_code(kind)) {
+                continue;
+            }
+            S::open(kind, &mut output);
+            open.push((end, kind));
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lengths() {
+        assert_eq!(utf16_len(""), 0);
+        assert_eq!(utf16_len("hello"), 5);
+
+        // CJK characters take 3 bytes, but a single unit.
+        assert_eq!("日本語".len(), 9);
+        assert_eq!(utf16_len("日本語"), 3);
+
+        // Emoji outside the basic plane take 4 bytes and two units.
+        assert_eq!("🦀".len(), 4);
+        assert_eq!(utf16_len("🦀"), 2);
+        // And ones made of several characters add up.
+        assert_eq!("👍🏽".len(), 8);
+        assert_eq!(utf16_len("👍🏽"), 4);
+    }
+
+    #[test]
+    fn entities_to_markup() {
+        let url: reqwest::Url = "https://example.com/a_(b)".parse().unwrap();
+        let entities = |pairs: &[(MessageEntityKind, usize, usize)]| -> Vec<MessageEntity> {
+            pairs
+                .iter()
+                .map(|(kind, offset, length)| MessageEntity::new(kind.clone(), *offset, *length))
+                .collect()
+        };
+
+        // Bold inside of a link.
+        let text = "see the docs now";
+        let link = entities(&[
+            (MessageEntityKind::TextLink { url: url.clone() }, 4, 8),
+            (MessageEntityKind::Bold, 8, 4),
+        ]);
+        assert_eq!(
+            entities_to_markdown(text, &link),
+            "see [the *docs*](https://example.com/a_(b\\)) now"
+        );
+        assert_eq!(
+            entities_to_html(text, &link),
+            "see <a href=\"https://example.com/a_(b)\">the <b>docs</b></a> now"
+        );
+
+        // Overlapping without nesting, after an emoji of 2 UTF-16 units.
+        let text = "🦀 bold and italic";
+        let overlap = entities(&[
+            (MessageEntityKind::Bold, 3, 8),
+            (MessageEntityKind::Italic, 8, 10),
+        ]);
+        assert_eq!(
+            entities_to_markdown(text, &overlap),
+            "🦀 *bold _and_*_ italic_"
+        );
+        assert_eq!(
+            entities_to_html(text, &overlap),
+            "🦀 <b>bold <i>and</i></b><i> italic</i>"
+        );
+
+        // Special characters get escaped, differently inside of code.
+        let text = "1+1=2. `a\\b` <&>";
+        let code = entities(&[
+            (MessageEntityKind::Code, 7, 5),
+            (MessageEntityKind::Bold, 8, 1),
+        ]);
+        assert_eq!(
+            entities_to_markdown(text, &code),
+            "1\\+1\\=2\\. `\\`a\\\\b\\`` <&\\>"
+        );
+        assert_eq!(
+            entities_to_html(text, &code),
+            "1+1=2. <code>`a\\b`</code> &lt;&amp;&gt;"
+        );
+
+        // Italic right next to underline is told apart.
+        let both = entities(&[
+            (MessageEntityKind::Italic, 0, 1),
+            (MessageEntityKind::Underline, 0, 1),
+        ]);
+        assert_eq!(entities_to_markdown("x", &both), "_\r__x__\r_");
+
+        let pre = entities(&[(
+            MessageEntityKind::Pre {
+                language: Some("rust".into()),
+            },
+            0,
+            6,
+        )]);
+        assert_eq!(entities_to_markdown("let x;", &pre), "```rust\nlet x;```");
+        assert_eq!(
+            entities_to_html("let x;", &pre),
+            "<pre><code class=\"language-rust\">let x;</code></pre>"
+        );
+
+        // Entities Telegram finds by itself are just text.
+        let mention = entities(&[(MessageEntityKind::Mention, 0, 5)]);
+        assert_eq!(entities_to_markdown("@crab", &mention), "@crab");
+    }
+
+    #[test]
+    fn entities_past_the_end_stop_at_it() {
+        let bold = [MessageEntity::bold(2, 10)];
+        assert_eq!(entities_to_markdown("a bc", &bold), "a *bc*");
+        assert_eq!(entities_to_html("a bc", &bold), "a <b>bc</b>");
+    }
+
+    #[test]
+    fn truncation() {
+        assert_eq!(truncate_utf16("hello", 3), "hel");
+        assert_eq!(truncate_utf16("hello", 10), "hello");
+        assert_eq!(truncate_utf16("日本語", 2), "日本");
+        assert_eq!(truncate_utf16("a🦀b", 3), "a🦀");
+        // Half of a surrogate pair doesn't fit.
+        assert_eq!(truncate_utf16("a🦀b", 2), "a");
+        assert_eq!(truncate_utf16("🦀", 0), "");
+    }
+}
@@ -0,0 +1,200 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use futures::Future;
+use teloxide::{
+    types::{File, FileMeta},
+    Bot, RequestError,
+};
+
+use super::FileResolver;
+
+/// A [`Bot`] that remembers results of [`get_file`] for a while,
+/// so that downloading the same file again (like a sticker that gets sent
+/// a lot) doesn't need another request. All of [`super::BotStuff`] works
+/// with it the same way it works with a plain [`Bot`].
+///
+/// Clones share the same cache.
+///
+/// [`get_file`]: teloxide::requests::Requester::get_file
+#[derive(Clone)]
+pub struct CachedBot {
+    bot: Bot,
+    cache: Arc<FileCache>,
+}
+
+impl CachedBot {
+    /// Telegram guarantees download links to be valid for at least an hour.
+    /// Stay well under that, in case the file was resolved a bit before caching.
+    pub const DEFAULT_TTL: Duration = Duration::from_secs(50 * 60);
+    pub const DEFAULT_CAPACITY: usize = 1024;
+
+    /// Wrap a bot with a cache of [`CachedBot::DEFAULT_CAPACITY`] files,
+    /// each kept for [`CachedBot::DEFAULT_TTL`].
+    #[must_use]
+    pub fn new(bot: Bot) -> Self {
+        Self::with_ttl_and_capacity(bot, Self::DEFAULT_TTL, Self::DEFAULT_CAPACITY)
+    }
+
+    /// Wrap a bot with a cache of `capacity` files, each kept for `ttl`.
+    /// When the cache is full, the least recently used file is forgotten.
+    #[must_use]
+    pub fn with_ttl_and_capacity(bot: Bot, ttl: Duration, capacity: usize) -> Self {
+        CachedBot {
+            bot,
+            cache: Arc::new(FileCache::new(ttl, capacity)),
+        }
+    }
+}
+
+impl FileResolver for CachedBot {
+    fn bot(&self) -> &Bot {
+        &self.bot
+    }
+    async fn resolve_file(&self, file: &FileMeta) -> Result<File, RequestError> {
+        self.cache
+            .get_or_resolve(&file.id, || self.bot.resolve_file(file))
+            .await
+    }
+}
+
+struct CacheEntry {
+    file: File,
+    resolved_at: Instant,
+    last_used: Instant,
+}
+
+/// Time-bounded LRU cache of files by their IDs.
+struct FileCache {
+    ttl: Duration,
+    capacity: usize,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+}
+
+impl FileCache {
+    fn new(ttl: Duration, capacity: usize) -> Self {
+        FileCache {
+            ttl,
+            capacity,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn get(&self, id: &str) -> Option<File> {
+        let mut entries = self.entries.lock().expect("File cache lock got poisoned");
+        let now = Instant::now();
+        let entry = entries.get_mut(id)?;
+        if now.duration_since(entry.resolved_at) >= self.ttl {
+            entries.remove(id);
+            return None;
+        }
+        entry.last_used = now;
+        Some(entry.file.clone())
+    }
+
+    fn insert(&self, id: String, file: File) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().expect("File cache lock got poisoned");
+        let now = Instant::now();
+        entries.retain(|_, entry| now.duration_since(entry.resolved_at) < self.ttl);
+
+        if entries.len() >= self.capacity && !entries.contains_key(&id) {
+            let least_recently_used = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(id, _)| id.clone());
+            if let Some(id) = least_recently_used {
+                entries.remove(&id);
+            }
+        }
+
+        entries.insert(
+            id,
+            CacheEntry {
+                file,
+                resolved_at: now,
+                last_used: now,
+            },
+        );
+    }
+
+    /// Returns the cached file with this ID, or caches what `resolve` returns.
+    async fn get_or_resolve<Fut>(
+        &self,
+        id: &str,
+        resolve: impl FnOnce() -> Fut,
+    ) -> Result<File, RequestError>
+    where
+        Fut: Future<Output = Result<File, RequestError>>,
+    {
+        if let Some(file) = self.get(id) {
+            return Ok(file);
+        }
+        let file = resolve().await?;
+        self.insert(id.to_string(), file.clone());
+        Ok(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(id: &str) -> File {
+        serde_json::from_value(serde_json::json!({
+            "file_id": id,
+            "file_unique_id": id,
+            "file_size": 1000,
+            "file_path": format!("stickers/{id}.webp"),
+        }))
+        .unwrap()
+    }
+
+    /// Resolves through the cache, returning if the "request" was made.
+    async fn resolve(cache: &FileCache, id: &str) -> bool {
+        let mut requested = false;
+        let resolved = cache
+            .get_or_resolve(id, || {
+                requested = true;
+                async { Ok(file(id)) }
+            })
+            .await
+            .unwrap();
+        assert_eq!(resolved.meta.id, id);
+        requested
+    }
+
+    #[tokio::test]
+    async fn second_lookup_is_cached() {
+        let cache = FileCache::new(Duration::from_secs(60), 10);
+        assert!(resolve(&cache, "a").await);
+        assert!(!resolve(&cache, "a").await);
+        assert!(resolve(&cache, "b").await);
+    }
+
+    #[tokio::test]
+    async fn entries_expire() {
+        let cache = FileCache::new(Duration::from_millis(20), 10);
+        assert!(resolve(&cache, "a").await);
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(resolve(&cache, "a").await);
+    }
+
+    #[tokio::test]
+    async fn least_recently_used_is_evicted() {
+        let cache = FileCache::new(Duration::from_secs(60), 2);
+        assert!(resolve(&cache, "a").await);
+        assert!(resolve(&cache, "b").await);
+        // Use "a" so that "b" becomes the least recently used.
+        assert!(!resolve(&cache, "a").await);
+        assert!(resolve(&cache, "c").await);
+
+        assert!(!resolve(&cache, "a").await);
+        assert!(resolve(&cache, "b").await);
+    }
+}
@@ -0,0 +1,118 @@
+use std::sync::Arc;
+
+use teloxide::{
+    types::{File, FileMeta},
+    Bot, RequestError,
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use super::FileResolver;
+
+/// A [`Bot`] that runs at most a set number of downloads at once, so that
+/// a flood of media doesn't make the bot run out of memory or connections.
+/// Downloads over the limit wait for others to finish first.
+///
+/// Every download of [`super::BotStuff`] counts, including streams, which
+/// take up their place until they are dropped.
+///
+/// Clones share the same limit.
+#[derive(Clone)]
+pub struct DownloadPool {
+    bot: Bot,
+    semaphore: Arc<Semaphore>,
+}
+
+impl DownloadPool {
+    /// A handful of downloads at once is plenty to keep a bot busy,
+    /// while keeping at most that many files in memory.
+    pub const DEFAULT_LIMIT: usize = 8;
+
+    /// Wrap a bot with the limit of [`DownloadPool::DEFAULT_LIMIT`].
+    #[must_use]
+    pub fn new(bot: Bot) -> Self {
+        Self::with_limit(bot, Self::DEFAULT_LIMIT)
+    }
+
+    /// Wrap a bot that runs at most `limit` downloads at once.
+    ///
+    /// # Panics
+    /// Panics if the limit is zero.
+    #[must_use]
+    pub fn with_limit(bot: Bot, limit: usize) -> Self {
+        assert!(limit > 0, "Download limit must be above zero");
+        DownloadPool {
+            bot,
+            semaphore: Arc::new(Semaphore::new(limit)),
+        }
+    }
+}
+
+impl FileResolver for DownloadPool {
+    fn bot(&self) -> &Bot {
+        &self.bot
+    }
+    async fn resolve_file(&self, file: &FileMeta) -> Result<File, RequestError> {
+        self.bot.resolve_file(file).await
+    }
+    async fn download_permit(&self) -> Option<OwnedSemaphorePermit> {
+        // The semaphore is never closed, so this always succeeds.
+        self.semaphore.clone().acquire_owned().await.ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::test_utils::{ok, MockServer};
+    use crate::useful_methods::BotStuff;
+
+    #[tokio::test]
+    async fn downloads_are_capped() {
+        let server = MockServer::new(|request| match request.method.as_str() {
+            "GetFile" => ok(serde_json::json!({
+                "file_id": "id",
+                "file_unique_id": "id",
+                "file_size": 3,
+                "file_path": "documents/file_1.pdf",
+            })),
+            _ => serde_json::json!("abc"),
+        })
+        .await;
+        let pool = DownloadPool::with_limit(server.bot.clone(), 2);
+        let active = Arc::new(AtomicUsize::new(0));
+        let max_active = Arc::new(AtomicUsize::new(0));
+
+        let tasks: Vec<_> = (0..6)
+            .map(|_| {
+                let pool = pool.clone();
+                let active = active.clone();
+                let max_active = max_active.clone();
+                tokio::spawn(async move {
+                    let file: FileMeta = serde_json::from_value(serde_json::json!({
+                        "file_id": "id", "file_unique_id": "id", "file_size": 3,
+                    }))
+                    .unwrap();
+                    let mut stream = std::pin::pin!(pool.download_file_as_stream(&file));
+                    // Once a chunk arrives, the download is surely running.
+                    stream.next().await.unwrap().unwrap();
+                    let now_active = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_active.fetch_max(now_active, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    active.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(max_active.load(Ordering::SeqCst), 2);
+    }
+}
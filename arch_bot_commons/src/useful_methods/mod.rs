@@ -1,17 +1,43 @@
+mod cached_bot;
+mod download_pool;
+mod rate_limited_bot;
 mod split_msg;
-use std::path::PathBuf;
+use std::{
+    future::IntoFuture,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
+pub use cached_bot::*;
+pub use download_pool::*;
+pub use rate_limited_bot::*;
 pub use split_msg::*;
 
-use futures::{Future, TryStreamExt};
+use bytes::{BufMut, Bytes, BytesMut};
+use chrono::{DateTime, Utc};
+use futures::{Future, Stream, StreamExt, TryStreamExt};
+use mime::Mime;
 use teloxide::{
     net::Download,
-    requests::Requester,
-    types::{ChatId, FileMeta, Message, PhotoSize},
+    payloads::{SendMessage, SendMessageSetters},
+    requests::{JsonRequest, Requester},
+    types::{
+        Animation, Audio, Chat, ChatAction, ChatId, Contact, Dice, Document, File, FileMeta,
+        ForwardedFrom, InputFile, Location, MediaKind as TgMediaKind, Message, MessageEntity,
+        MessageEntityKind, MessageId, MessageKind, ParseMode, PhotoSize, Poll, Sticker, User,
+        UserId, Venue, Video, VideoNote, Voice,
+    },
     Bot, RequestError,
 };
 use tempfile::NamedTempFile;
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt, BufWriter},
+    sync::{OwnedSemaphorePermit, Semaphore},
+};
+use tokio_util::io::ReaderStream;
 
+#[derive(Clone, Debug)]
 pub struct MessageMediaInfo<'a> {
     pub width: u32,
     pub height: u32,
@@ -22,215 +48,4690 @@ pub struct MessageMediaInfo<'a> {
     pub is_sound: bool,
     pub is_voice_or_video_note: bool,
     pub is_vector_sticker: bool,
+    /// What the media was sent as.
+    pub kind: MediaKind,
+    /// Duration of the media in seconds, if it's something that plays.
+    pub duration: Option<u32>,
+    /// Declared MIME type of the file. For photos and stickers, which
+    /// Telegram provides none for, this is guessed from their format.
+    pub mime_type: Option<Mime>,
     pub file: &'a FileMeta,
+    /// Small preview of the media, if Telegram made one.
+    pub thumb: Option<&'a FileMeta>,
+    /// Dimensions of the preview, if there's one.
+    pub thumb_width: Option<u32>,
+    pub thumb_height: Option<u32>,
+}
+
+/// Same as [`MessageMediaInfo`], but owns its files, so it can be kept
+/// around after the message is gone. Made by [`MessageMediaInfo::to_owned`].
+///
+/// With the `serde` feature, this can be serialized. When storing it,
+/// key it by `file.unique_id`, since file IDs can change over time.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OwnedMediaInfo {
+    pub width: u32,
+    pub height: u32,
+    pub is_sticker: bool,
+    pub is_gif: bool,
+    pub is_video: bool,
+    pub is_image: bool,
+    pub is_sound: bool,
+    pub is_voice_or_video_note: bool,
+    pub is_vector_sticker: bool,
+    pub kind: MediaKind,
+    pub duration: Option<u32>,
+    #[cfg_attr(feature = "serde", serde(with = "optional_mime"))]
+    pub mime_type: Option<Mime>,
+    pub file: FileMeta,
+    pub thumb: Option<FileMeta>,
+    pub thumb_width: Option<u32>,
+    pub thumb_height: Option<u32>,
+}
+
+/// [`Mime`] doesn't implement serde traits, so it's stored as a string.
+#[cfg(feature = "serde")]
+mod optional_mime {
+    use mime::Mime;
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(mime: &Option<Mime>, s: S) -> Result<S::Ok, S::Error> {
+        mime.as_ref().map(Mime::as_ref).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Mime>, D::Error> {
+        Option::<String>::deserialize(d)?
+            .map(|x| x.parse().map_err(D::Error::custom))
+            .transpose()
+    }
+}
+
+impl OwnedMediaInfo {
+    /// Borrow this as a [`MessageMediaInfo`], to use its methods.
+    #[must_use]
+    pub fn as_info(&self) -> MessageMediaInfo<'_> {
+        MessageMediaInfo {
+            width: self.width,
+            height: self.height,
+            is_sticker: self.is_sticker,
+            is_gif: self.is_gif,
+            is_video: self.is_video,
+            is_image: self.is_image,
+            is_sound: self.is_sound,
+            is_voice_or_video_note: self.is_voice_or_video_note,
+            is_vector_sticker: self.is_vector_sticker,
+            kind: self.kind,
+            duration: self.duration,
+            mime_type: self.mime_type.clone(),
+            file: &self.file,
+            thumb: self.thumb.as_ref(),
+            thumb_width: self.thumb_width,
+            thumb_height: self.thumb_height,
+        }
+    }
+}
+
+/// Error returned by [`MessageMediaInfo::require_raster`].
+#[derive(Clone, Copy, Debug)]
+pub struct NotRasterError;
+
+impl std::fmt::Display for NotRasterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The media is not an image or a video")
+    }
+}
+
+impl std::error::Error for NotRasterError {}
+
+/// What a piece of media was sent as, which corresponds to a `send_*` method.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MediaKind {
+    Photo,
+    Sticker,
+    Video,
+    /// A GIF, which is actually a silent video.
+    Animation,
+    /// A round video.
+    VideoNote,
+    Voice,
+    Audio,
+    /// Any file, which might still be an image, video or sound.
+    Document,
+}
+
+/// The three formats a sticker can be in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StickerKind {
+    /// A WEBP image.
+    Static,
+    /// A vector animation in TGS (Lottie) format.
+    Animated,
+    /// A WEBM video.
+    Video,
+}
+
+impl StickerKind {
+    /// Extension of files of this format, without the dot.
+    pub fn file_extension(self) -> &'static str {
+        match self {
+            StickerKind::Static => "webp",
+            StickerKind::Animated => "tgs",
+            StickerKind::Video => "webm",
+        }
+    }
 }
 
 impl MessageMediaInfo<'_> {
+    /// Copy this into an [`OwnedMediaInfo`], cloning the files.
+    #[must_use]
+    pub fn to_owned(&self) -> OwnedMediaInfo {
+        OwnedMediaInfo {
+            width: self.width,
+            height: self.height,
+            is_sticker: self.is_sticker,
+            is_gif: self.is_gif,
+            is_video: self.is_video,
+            is_image: self.is_image,
+            is_sound: self.is_sound,
+            is_voice_or_video_note: self.is_voice_or_video_note,
+            is_vector_sticker: self.is_vector_sticker,
+            kind: self.kind,
+            duration: self.duration,
+            mime_type: self.mime_type.clone(),
+            file: self.file.clone(),
+            thumb: self.thumb.cloned(),
+            thumb_width: self.thumb_width,
+            thumb_height: self.thumb_height,
+        }
+    }
+
+    /// Make an [`InputFile`] that sends this same file again by its ID,
+    /// without downloading it.
+    #[must_use]
+    pub fn to_input_file(&self) -> InputFile {
+        InputFile::file_id(self.file.id.clone())
+    }
+
+    /// Make an [`InputFile`] that uploads `bytes` in place of this file,
+    /// like after editing it. Use [`MessageMediaInfo::to_input_file`]
+    /// to send it as it is.
+    #[must_use]
+    pub fn to_input_file_bytes(&self, bytes: Vec<u8>) -> InputFile {
+        InputFile::memory(bytes)
+    }
+
+    /// Returns which kind of sticker this is, or `None` if it's not a sticker.
+    pub fn sticker_kind(&self) -> Option<StickerKind> {
+        if !self.is_sticker {
+            return None;
+        }
+        Some(if self.is_vector_sticker {
+            StickerKind::Animated
+        } else if self.is_video {
+            StickerKind::Video
+        } else {
+            StickerKind::Static
+        })
+    }
+    /// Returns the extension of the sticker's file, without the dot,
+    /// or `None` if it's not a sticker. See [`StickerKind::file_extension`].
+    pub fn sticker_file_extension(&self) -> Option<&'static str> {
+        self.sticker_kind().map(StickerKind::file_extension)
+    }
     pub fn is_image(&self) -> bool {
         !self.is_video && self.is_raster()
     }
     pub fn is_raster(&self) -> bool {
-        !self.is_vector_sticker && !self.is_sound
+        // Documents of unknown type are neither images nor videos.
+        !self.is_vector_sticker && !self.is_sound && (self.is_image || self.is_video)
+    }
+    /// ID of the file that's the same for every bot and doesn't change,
+    /// so it can be used to recognize the same file across time.
+    ///
+    /// Unlike `file.id`, it can't be used to download or send the file.
+    /// `file.id` is the other way around: it's needed to download
+    /// or send the file, but can change, so it shouldn't be used as a key
+    /// for caches or deduplication.
+    pub fn unique_id(&self) -> &str {
+        &self.file.unique_id
+    }
+    /// Returns the media back if it's an image or a video.
+    ///
+    /// # Errors
+    /// Returns [`NotRasterError`] if it's anything else, like a vector
+    /// sticker, a voice message or a document of unknown type.
+    pub fn require_raster(self) -> Result<Self, NotRasterError> {
+        match self.is_raster() {
+            true => Ok(self),
+            false => Err(NotRasterError),
+        }
+    }
+    /// Whether this is a video note, the round kind of video.
+    pub fn is_round_video(&self) -> bool {
+        self.is_voice_or_video_note && self.is_video
+    }
+    /// Whether this can have sound: voice messages, video notes,
+    /// videos and audio. GIFs and video stickers are silent.
+    pub fn has_sound(&self) -> bool {
+        self.is_sound || (self.is_video && !self.is_gif && !self.is_sticker)
+    }
+    /// Whether this is a video, GIF or video sticker, which
+    /// [`crate::media::to_gif`] can convert into a GIF.
+    pub fn can_convert_to_gif(&self) -> bool {
+        self.is_gif || self.is_video
     }
+    /// Size of the file in bytes, as reported by Telegram.
+    pub fn file_size(&self) -> u32 {
+        self.file.size
+    }
+    /// Returns `true` if the file is bigger than `max_bytes`.
+    /// Useful to reject oversized media before downloading it.
+    pub fn exceeds(&self, max_bytes: u32) -> bool {
+        self.file_size() > max_bytes
+    }
+    /// Width divided by height, or `None` if the media has no dimensions.
+    pub fn aspect_ratio(&self) -> Option<f32> {
+        if self.height == 0 {
+            return None;
+        }
+        Some(self.width as f32 / self.height as f32)
+    }
+    /// Returns `true` if the media is taller than it is wide.
+    pub fn is_portrait(&self) -> bool {
+        self.height > self.width
+    }
+    /// Returns `true` if the media is wider than it is tall.
+    pub fn is_landscape(&self) -> bool {
+        self.width > self.height
+    }
+    /// Returns `true` if the media has equal, non-zero width and height.
+    pub fn is_square(&self) -> bool {
+        self.width == self.height && self.width != 0
+    }
+}
+
+/// Extra information about a sticker that doesn't fit in [`MessageMediaInfo`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StickerMeta<'a> {
+    pub emoji: Option<&'a str>,
+    pub set_name: Option<&'a str>,
+    /// `true` if the sticker has a premium animation.
+    pub is_premium: bool,
+}
+
+/// A user mentioned in a message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mention {
+    /// Username of the user, without the `@`.
+    pub username: Option<String>,
+    /// ID of the user. Only known if they were mentioned by a link,
+    /// which is the case for users without a username.
+    pub user: Option<UserId>,
+}
+
+/// A bot command parsed out of a message by [`MessageStuff::parse_command`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParsedCommand<'a> {
+    /// Name of the command, without the `/`.
+    pub command: &'a str,
+    /// Username of the bot the command is addressed to, without the `@`,
+    /// if it was specified.
+    pub target: Option<&'a str>,
+    /// Everything after the command, with whitespace trimmed.
+    pub args: &'a str,
+}
+
+/// Where a forwarded message originally came from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ForwardOrigin<'a> {
+    /// Sent by a user.
+    User(&'a User),
+    /// Sent by a user who hides their account in forwards.
+    /// Only their name is known.
+    HiddenUser(&'a str),
+    /// Posted in a channel.
+    Channel {
+        chat: &'a Chat,
+        /// ID of the original post in the channel.
+        message_id: Option<MessageId>,
+        /// Signature of the post author, if the channel has them enabled.
+        signature: Option<&'a str>,
+    },
+    /// Sent by an anonymous admin on behalf of a group.
+    Chat {
+        chat: &'a Chat,
+        /// Custom title of the admin, if they have one.
+        signature: Option<&'a str>,
+    },
+}
+
+/// Who sent a message, see [`MessageStuff::effective_sender`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EffectiveSender<'a> {
+    /// Sent by a user, or a bot.
+    User(&'a User),
+    /// Sent on behalf of a chat: posted in a channel, sent by an anonymous
+    /// admin of a group, or forwarded from a linked channel.
+    Chat(&'a Chat),
+    /// Telegram didn't say who sent it.
+    None,
+}
+
+/// What a message contains, see [`MessageStuff::content_kind`].
+#[derive(Debug)]
+pub enum MessageContentKind<'a> {
+    Text(&'a str),
+    Media(MessageMediaInfo<'a>),
+    Poll(&'a Poll),
+    Dice(&'a Dice),
+    Location(&'a Location),
+    Contact(&'a Contact),
+    Venue(&'a Venue),
+    /// A message about something happening in the chat, like
+    /// a user joining or a message getting pinned.
+    Service,
+    /// Anything else, like games and invoices.
+    Other,
 }
 
 pub trait MessageStuff {
     fn text_full(&self) -> Option<&str>;
-    #[allow(clippy::result_unit_err)] // i'm lazy lol
-    /// On success, returns info about image/video/sound in the video,
-    /// as well as bool that is `true` if it's a sticker.
+    /// Like [`MessageStuff::text_full`], but also returns the entities
+    /// belonging to that text, i.e. caption entities for a caption.
+    fn text_full_with_entities(&self) -> Option<(&str, &[MessageEntity])>;
+    /// Returns all links in the message's text or caption, both those
+    /// written out and those hidden behind text.
+    fn extract_urls(&self) -> Vec<String>;
+    /// Returns all users mentioned in the message's text or caption,
+    /// whether by `@username` or by a link to a user without one.
+    fn extract_mentions(&self) -> Vec<Mention>;
+    /// Returns all hashtags in the message's text or caption, as written,
+    /// including the `#`.
+    fn extract_hashtags(&self) -> Vec<String>;
+    /// Parses a bot command at the start of the message's text or caption, like
+    /// `/ban@mybot 123 spam`. Commands explicitly addressed to any bot
+    /// other than the one with `bot_username` are ignored.
+    fn parse_command(&self, bot_username: &str) -> Option<ParsedCommand<'_>>;
+    /// Returns everything after the bot command at the start of the message's
+    /// text or caption, like [`ParsedCommand::args`], without allocating.
+    /// Only the whitespace right after the command is trimmed, so
+    /// anything at the end, like trailing newlines, is left as is.
+    fn command_args_raw(&self, bot_username: &str) -> Option<&str>;
+    /// Returns a `t.me` link to this message. Links to messages in private
+    /// supergroups and channels only work for their members.
     ///
-    /// # Errors
-    /// Returns Err(()) if there is a sticker but it's not raster.
+    /// Returns `None` for private chats and basic groups, which
+    /// can't be linked to.
+    fn permalink(&self) -> Option<String>;
+    /// Returns `true` if this message is forwarded from somewhere.
+    fn is_forwarded(&self) -> bool;
+    /// Returns where this message was forwarded from, if it was.
+    fn forward_origin(&self) -> Option<ForwardOrigin<'_>>;
+    /// Returns the media in this message together with where it was
+    /// forwarded from, if this is a forward with media in it. Unlike
+    /// [`MessageStuff::get_media_info`], this doesn't look at the message
+    /// it replies to, since that media wasn't forwarded.
+    fn forwarded_media(&self) -> Option<(MessageMediaInfo<'_>, ForwardOrigin<'_>)>;
+    /// Returns an iterator over the message this one replies to, the message
+    /// that one replies to, and so on, for as long as they are available.
+    ///
+    /// Note that Telegram itself only includes one level of replies.
+    /// The chain stops at a message that was already in it, and after
+    /// 64 messages.
+    fn reply_chain(&self) -> impl Iterator<Item = &Message>;
+    /// Returns the deepest available message in the reply chain,
+    /// or this message itself if it's not a reply.
+    fn root_message(&self) -> &Message;
+    /// Returns a human-readable name of whoever sent this message: the full
+    /// name of the user, or their `@username` if they have no name, or the
+    /// author's signature or the chat's name for posts made on behalf of one.
+    ///
+    /// Unlike [`crate::print_sender`], this doesn't try to tag anyone.
+    fn sender_display_name(&self) -> Option<String>;
+    /// Returns who sent this message, the same way in private chats,
+    /// groups and channels. Messages sent on behalf of a chat count as sent
+    /// by that chat, not by the placeholder user Telegram puts there.
+    fn effective_sender(&self) -> EffectiveSender<'_>;
+    /// Returns the chat this message is in.
+    fn effective_chat(&self) -> &Chat;
+    /// Returns info about the image, video or sound in the message,
+    /// or in the message it replies to if this one has none.
+    ///
+    /// Every kind of media is returned, including vector stickers and voice
+    /// messages. Use [`MessageMediaInfo::require_raster`] to only accept
+    /// images and videos.
     fn get_media_info(&self) -> Option<MessageMediaInfo<'_>>;
+    /// Returns what this message contains. Unlike [`MessageStuff::get_media_info`],
+    /// this doesn't look at the message it replies to.
+    fn content_kind(&self) -> MessageContentKind<'_>;
+    /// Whether [`MessageStuff::get_media_info`] would return anything,
+    /// without putting together the info.
+    fn has_media(&self) -> bool;
+    /// Returns the size of the photo with the most pixels.
+    /// Out of sizes with the same amount of pixels, the one
+    /// with the biggest file wins.
     fn find_biggest_photo(&self) -> Option<&PhotoSize>;
+    /// Returns the size of the photo with the least pixels.
+    /// Out of sizes with the same amount of pixels, the one
+    /// with the smallest file wins.
+    fn find_smallest_photo(&self) -> Option<&PhotoSize>;
+    /// Returns the size of the photo with the most pixels out of those
+    /// whose file is at most `max_bytes` big. If none are that small,
+    /// returns [`MessageStuff::find_smallest_photo`] instead.
+    fn best_photo_under(&self, max_bytes: u32) -> Option<&PhotoSize>;
+    /// Returns all sizes of the photo in this message, if it has one.
+    /// The slice is not sorted in any particular order.
+    fn get_all_photos(&self) -> Option<&[PhotoSize]>;
+    /// Returns the ID of the album this message is a part of, if any.
+    fn media_group_id(&self) -> Option<&str>;
+    /// Returns the emoji and sticker set of the sticker in this message,
+    /// or in the message it replies to.
+    fn sticker_meta(&self) -> Option<StickerMeta<'_>>;
+    /// Whether any of the text or caption is hidden as a spoiler,
+    /// or the media is covered by one.
+    fn has_spoiler(&self) -> bool;
+    /// Whether the bot can probably still edit this message. It has to be
+    /// sent by a bot, not be a forward, have text or media (not a poll, or
+    /// a dice, or a service message...), and be less than 48 hours old.
+    ///
+    /// This can't tell which bot sent it, so only use it on messages sent by
+    /// this one. Telegram can still refuse some edits, like changing media
+    /// into a kind it can't be changed into.
+    fn is_editable_by_me(&self, now: DateTime<Utc>) -> bool;
+    /// Returns when this message was last edited, or when it was sent
+    /// if it never was.
+    fn effective_date(&self) -> DateTime<Utc>;
+    /// Returns `true` if this message was edited after being sent.
+    fn was_edited(&self) -> bool;
 }
 
 impl MessageStuff for Message {
     fn text_full(&self) -> Option<&str> {
         self.text().or_else(|| self.caption())
     }
+    fn text_full_with_entities(&self) -> Option<(&str, &[MessageEntity])> {
+        if let Some(text) = self.text() {
+            return Some((text, self.entities().unwrap_or_default()));
+        }
+        let caption = self.caption()?;
+        Some((caption, self.caption_entities().unwrap_or_default()))
+    }
+    fn extract_urls(&self) -> Vec<String> {
+        let Some(ents) = self
+            .parse_entities()
+            .or_else(|| self.parse_caption_entities())
+        else {
+            return Vec::new();
+        };
+        ents.iter()
+            .filter_map(|ent| {
+                use teloxide::types::MessageEntityKind::*;
+                match ent.kind() {
+                    Url => Some(ent.text().to_string()),
+                    TextLink { url } => Some(url.to_string()),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+    fn extract_mentions(&self) -> Vec<Mention> {
+        let Some(ents) = self
+            .parse_entities()
+            .or_else(|| self.parse_caption_entities())
+        else {
+            return Vec::new();
+        };
+        ents.iter()
+            .filter_map(|ent| match ent.kind() {
+                MessageEntityKind::Mention => Some(Mention {
+                    username: Some(ent.text().trim_start_matches('@').to_string()),
+                    user: None,
+                }),
+                MessageEntityKind::TextMention { user } => Some(Mention {
+                    username: user.username.clone(),
+                    user: Some(user.id),
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+    fn extract_hashtags(&self) -> Vec<String> {
+        let Some(ents) = self
+            .parse_entities()
+            .or_else(|| self.parse_caption_entities())
+        else {
+            return Vec::new();
+        };
+        ents.iter()
+            .filter(|ent| matches!(ent.kind(), MessageEntityKind::Hashtag))
+            .map(|ent| ent.text().to_string())
+            .collect()
+    }
+    fn parse_command(&self, bot_username: &str) -> Option<ParsedCommand<'_>> {
+        let mut command = untrimmed_command(self, bot_username)?;
+        command.args = command.args.trim();
+        Some(command)
+    }
+    fn command_args_raw(&self, bot_username: &str) -> Option<&str> {
+        let command = untrimmed_command(self, bot_username)?;
+        Some(command.args.trim_start())
+    }
+    fn permalink(&self) -> Option<String> {
+        // teloxide handles the supergroup chat ID conversion for us.
+        self.url().map(String::from)
+    }
+    fn is_forwarded(&self) -> bool {
+        self.forward().is_some()
+    }
+    fn forwarded_media(&self) -> Option<(MessageMediaInfo<'_>, ForwardOrigin<'_>)> {
+        let origin = self.forward_origin()?;
+        Some((own_media_info(self)?, origin))
+    }
+    fn forward_origin(&self) -> Option<ForwardOrigin<'_>> {
+        let forward = self.forward()?;
+        Some(match &forward.from {
+            ForwardedFrom::User(user) => ForwardOrigin::User(user),
+            ForwardedFrom::SenderName(name) => ForwardOrigin::HiddenUser(name),
+            ForwardedFrom::Chat(chat) if chat.is_channel() => ForwardOrigin::Channel {
+                chat,
+                message_id: forward.message_id.map(MessageId),
+                signature: forward.signature.as_deref(),
+            },
+            ForwardedFrom::Chat(chat) => ForwardOrigin::Chat {
+                chat,
+                signature: forward.signature.as_deref(),
+            },
+        })
+    }
+    fn reply_chain(&self) -> impl Iterator<Item = &Message> {
+        // Stored or made up messages can reply to themselves,
+        // so don't trust the chain to end or not to loop.
+        let mut seen = vec![(self.chat.id, self.id)];
+        std::iter::successors(self.reply_to_message(), |x| x.reply_to_message())
+            .take(MAX_REPLY_DEPTH)
+            .take_while(move |x| {
+                let key = (x.chat.id, x.id);
+                if seen.contains(&key) {
+                    return false;
+                }
+                seen.push(key);
+                true
+            })
+    }
+    fn root_message(&self) -> &Message {
+        self.reply_chain().last().unwrap_or(self)
+    }
+    fn sender_display_name(&self) -> Option<String> {
+        if let Some(user) = self.from() {
+            let full_name = user.full_name();
+            if !full_name.trim().is_empty() {
+                return Some(full_name);
+            }
+            if let Some(username) = &user.username {
+                return Some(format!("@{username}"));
+            }
+        }
+        if let Some(signature) = self.author_signature() {
+            return Some(signature.to_string());
+        }
+        self.sender_chat().and_then(crate::print_chat)
+    }
+    fn effective_sender(&self) -> EffectiveSender<'_> {
+        if let Some(chat) = self.sender_chat() {
+            return EffectiveSender::Chat(chat);
+        }
+        match self.from() {
+            Some(user) => EffectiveSender::User(user),
+            None => EffectiveSender::None,
+        }
+    }
+    fn effective_chat(&self) -> &Chat {
+        &self.chat
+    }
     fn get_media_info(&self) -> Option<MessageMediaInfo<'_>> {
-        if let Some(biggest) = self.find_biggest_photo() {
-            return Some(MessageMediaInfo {
-                width: biggest.width,
-                height: biggest.height,
-                is_sticker: false,
-                is_gif: false,
-                is_video: false,
-                is_image: true,
-                is_sound: false,
-                is_voice_or_video_note: false,
-                is_vector_sticker: false,
-                file: &biggest.file,
-            });
-        }
-
-        if let Some(sticker) = self.sticker() {
-            return Some(MessageMediaInfo {
-                width: sticker.width.into(),
-                height: sticker.height.into(),
-                is_sticker: true,
-                is_gif: false,
-                is_video: sticker.is_video(),
-                is_sound: false,
-                is_image: !sticker.is_video() && !sticker.is_animated(),
-                is_voice_or_video_note: false,
-                is_vector_sticker: sticker.is_animated(),
-                file: &sticker.file,
-            });
-        }
-
-        if let Some(video) = self.video() {
-            return Some(MessageMediaInfo {
-                width: video.width,
-                height: video.height,
-                is_sticker: false,
-                is_gif: false,
-                is_video: true,
-                is_image: false,
-                is_sound: false,
-                is_voice_or_video_note: false,
-                is_vector_sticker: false,
-                file: &video.file,
-            });
-        }
-
-        if let Some(animation) = self.animation() {
-            return Some(MessageMediaInfo {
-                width: animation.width,
-                height: animation.height,
-                is_sticker: false,
-                is_video: true,
-                is_gif: true,
-                is_image: false,
-                is_sound: false,
-                is_voice_or_video_note: false,
-                is_vector_sticker: false,
-                file: &animation.file,
-            });
-        }
-
-        if let Some(video_note) = self.video_note() {
-            if let Some(thumb) = &video_note.thumb {
-                return Some(MessageMediaInfo {
-                    width: thumb.width,
-                    height: thumb.height,
-                    is_sticker: false,
-                    is_video: true,
-                    is_gif: false,
-                    is_image: false,
-                    is_sound: false,
-                    is_voice_or_video_note: true,
-                    is_vector_sticker: false,
-                    file: &video_note.file,
-                });
-            }
-        }
-
-        if let Some(voice) = self.voice() {
-            return Some(MessageMediaInfo {
-                width: 0,
-                height: 0,
-                is_sticker: false,
-                is_video: false,
-                is_gif: false,
-                is_image: false,
-                is_sound: true,
-                is_voice_or_video_note: true,
-                is_vector_sticker: false,
-                file: &voice.file,
-            });
-        }
-
-        if let Some(reply_to) = self.reply_to_message() {
-            return reply_to.get_media_info();
-        }
-
-        None
+        own_media_info(self).or_else(|| self.reply_chain().find_map(own_media_info))
+    }
+    fn content_kind(&self) -> MessageContentKind<'_> {
+        let common = match &self.kind {
+            MessageKind::Common(common) => common,
+            MessageKind::Dice(dice) => return MessageContentKind::Dice(&dice.dice),
+            MessageKind::Invoice(_)
+            | MessageKind::SuccessfulPayment(_)
+            | MessageKind::PassportData(_)
+            | MessageKind::WebAppData(_) => return MessageContentKind::Other,
+            _ => return MessageContentKind::Service,
+        };
+        match &common.media_kind {
+            TgMediaKind::Text(text) => MessageContentKind::Text(&text.text),
+            TgMediaKind::Poll(poll) => MessageContentKind::Poll(&poll.poll),
+            TgMediaKind::Location(location) => MessageContentKind::Location(&location.location),
+            TgMediaKind::Contact(contact) => MessageContentKind::Contact(&contact.contact),
+            TgMediaKind::Venue(venue) => MessageContentKind::Venue(&venue.venue),
+            TgMediaKind::Migration(_) => MessageContentKind::Service,
+            TgMediaKind::Game(_) => MessageContentKind::Other,
+            _ => own_media_info(self).map_or(MessageContentKind::Other, MessageContentKind::Media),
+        }
+    }
+    fn has_media(&self) -> bool {
+        has_own_media(self) || self.reply_chain().any(has_own_media)
     }
     fn find_biggest_photo(&self) -> Option<&PhotoSize> {
-        if let Some(photo_sizes) = self.photo() {
-            photo_sizes.iter().max_by_key(|x| x.width + x.height)
-        } else {
-            None
+        self.photo()?.iter().max_by_key(|x| photo_size_key(x))
+    }
+    fn find_smallest_photo(&self) -> Option<&PhotoSize> {
+        self.photo()?.iter().min_by_key(|x| photo_size_key(x))
+    }
+    fn best_photo_under(&self, max_bytes: u32) -> Option<&PhotoSize> {
+        self.photo()?
+            .iter()
+            .filter(|x| x.file.size <= max_bytes)
+            .max_by_key(|x| photo_size_key(x))
+            .or_else(|| self.find_smallest_photo())
+    }
+    fn get_all_photos(&self) -> Option<&[PhotoSize]> {
+        self.photo()
+    }
+    fn media_group_id(&self) -> Option<&str> {
+        Message::media_group_id(self)
+    }
+    fn sticker_meta(&self) -> Option<StickerMeta<'_>> {
+        own_sticker_meta(self).or_else(|| self.reply_chain().find_map(own_sticker_meta))
+    }
+    fn has_spoiler(&self) -> bool {
+        self.has_media_spoiler()
+            || self.text_full_with_entities().is_some_and(|(_, entities)| {
+                entities
+                    .iter()
+                    .any(|x| x.kind == MessageEntityKind::Spoiler)
+            })
+    }
+    fn is_editable_by_me(&self, now: DateTime<Utc>) -> bool {
+        let sent_by_bot = self.from().is_some_and(|x| x.is_bot);
+        let has_editable_content = matches!(
+            self.content_kind(),
+            MessageContentKind::Text(_) | MessageContentKind::Media(_)
+        );
+        let edit_window = chrono::Duration::hours(48);
+        sent_by_bot
+            && self.forward().is_none()
+            && has_editable_content
+            && now - self.date < edit_window
+    }
+    fn effective_date(&self) -> DateTime<Utc> {
+        self.edit_date().copied().unwrap_or(self.date)
+    }
+    fn was_edited(&self) -> bool {
+        self.edit_date().is_some()
+    }
+}
+
+/// Returns the text with everything covered by spoiler entities removed.
+pub fn strip_spoilers(text: &str, entities: &[MessageEntity]) -> String {
+    let spoilers: Vec<_> = entities
+        .iter()
+        .filter(|x| x.kind == MessageEntityKind::Spoiler)
+        .map(|x| x.offset..x.offset + x.length)
+        .collect();
+
+    let mut output = String::with_capacity(text.len());
+    // the offset in MessageEntity is a count of UTF-16 code units
+    let mut offset = 0;
+    for c in text.chars() {
+        if !spoilers.iter().any(|x| x.contains(&offset)) {
+            output.push(c);
+        }
+        offset += c.len_utf16();
+    }
+    output
+}
+
+/// Telegram sends albums as separate messages sharing a media group ID.
+/// This takes the group of the first message in `messages` that has one,
+/// and returns media info of all messages in that group, in order.
+/// Messages outside of that group are ignored.
+pub fn collect_media_group(messages: &[Message]) -> Vec<MessageMediaInfo<'_>> {
+    let Some(group_id) = messages.iter().find_map(MessageStuff::media_group_id) else {
+        return Vec::new();
+    };
+    messages
+        .iter()
+        .filter(|x| MessageStuff::media_group_id(*x) == Some(group_id))
+        .filter_map(MessageStuff::get_media_info)
+        .collect()
+}
+
+/// How many messages of each kind there are, see [`media_histogram`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MediaHistogram {
+    pub texts: usize,
+    pub photos: usize,
+    pub videos: usize,
+    pub animations: usize,
+    pub video_notes: usize,
+    pub voices: usize,
+    pub audios: usize,
+    pub documents: usize,
+    pub static_stickers: usize,
+    pub animated_stickers: usize,
+    pub video_stickers: usize,
+    pub polls: usize,
+    pub dice: usize,
+    pub locations: usize,
+    pub contacts: usize,
+    pub venues: usize,
+    pub service: usize,
+    pub other: usize,
+}
+
+/// Count messages by what they contain, as told by
+/// [`MessageStuff::content_kind`]. Media in replied to messages isn't counted.
+#[must_use]
+pub fn media_histogram(messages: &[Message]) -> MediaHistogram {
+    let mut histogram = MediaHistogram::default();
+    for message in messages {
+        let count = match message.content_kind() {
+            MessageContentKind::Text(_) => &mut histogram.texts,
+            MessageContentKind::Media(media) => match (media.kind, media.sticker_kind()) {
+                (MediaKind::Sticker, Some(StickerKind::Animated)) => {
+                    &mut histogram.animated_stickers
+                }
+                (MediaKind::Sticker, Some(StickerKind::Video)) => &mut histogram.video_stickers,
+                (MediaKind::Sticker, _) => &mut histogram.static_stickers,
+                (MediaKind::Photo, _) => &mut histogram.photos,
+                (MediaKind::Video, _) => &mut histogram.videos,
+                (MediaKind::Animation, _) => &mut histogram.animations,
+                (MediaKind::VideoNote, _) => &mut histogram.video_notes,
+                (MediaKind::Voice, _) => &mut histogram.voices,
+                (MediaKind::Audio, _) => &mut histogram.audios,
+                (MediaKind::Document, _) => &mut histogram.documents,
+            },
+            MessageContentKind::Poll(_) => &mut histogram.polls,
+            MessageContentKind::Dice(_) => &mut histogram.dice,
+            MessageContentKind::Location(_) => &mut histogram.locations,
+            MessageContentKind::Contact(_) => &mut histogram.contacts,
+            MessageContentKind::Venue(_) => &mut histogram.venues,
+            MessageContentKind::Service => &mut histogram.service,
+            MessageContentKind::Other => &mut histogram.other,
+        };
+        *count += 1;
+    }
+    histogram
+}
+
+/// How far [`MessageStuff::reply_chain`] goes at most.
+const MAX_REPLY_DEPTH: usize = 64;
+
+/// Like [`MessageStuff::has_media`], but doesn't look at the replied to message.
+fn has_own_media(message: &Message) -> bool {
+    message.photo().is_some_and(|x| !x.is_empty())
+        || message.sticker().is_some()
+        || message.video().is_some()
+        || message.animation().is_some()
+        || message.video_note().is_some_and(|x| x.thumb.is_some())
+        || message.voice().is_some()
+        || message.audio().is_some()
+        || message.document().is_some()
+}
+
+/// Like [`MessageStuff::sticker_meta`], but doesn't look at the replied to message.
+fn own_sticker_meta(message: &Message) -> Option<StickerMeta<'_>> {
+    let sticker = message.sticker()?;
+    Some(StickerMeta {
+        emoji: sticker.emoji.as_deref(),
+        set_name: sticker.set_name.as_deref(),
+        is_premium: sticker.premium_animation().is_some(),
+    })
+}
+
+/// Like [`MessageStuff::parse_command`], but leaves the args untrimmed.
+fn untrimmed_command<'a>(message: &'a Message, bot_username: &str) -> Option<ParsedCommand<'a>> {
+    // Commands can also be put in captions of media.
+    let ents = message
+        .parse_entities()
+        .or_else(|| message.parse_caption_entities())?;
+    let command = ents
+        .iter()
+        .find(|ent| ent.start() == 0 && matches!(ent.kind(), MessageEntityKind::BotCommand))?;
+
+    let (name, target) = match command.text().trim_start_matches('/').split_once('@') {
+        Some((name, target)) => (name, Some(target)),
+        None => (command.text().trim_start_matches('/'), None),
+    };
+
+    if let Some(target) = target {
+        let bot_username = bot_username.trim_start_matches('@');
+        if !target.eq_ignore_ascii_case(bot_username) {
+            return None;
         }
     }
+
+    Some(ParsedCommand {
+        command: name,
+        target,
+        args: &command.message_text()[command.end()..],
+    })
+}
+
+/// Media info of the message itself, without looking at what it replies to.
+fn own_media_info(message: &Message) -> Option<MessageMediaInfo<'_>> {
+    if let Some(biggest) = message.find_biggest_photo() {
+        return Some(media_info_from_photo(biggest));
+    }
+    if let Some(sticker) = message.sticker() {
+        return Some(media_info_from_sticker(sticker));
+    }
+    if let Some(video) = message.video() {
+        return Some(media_info_from_video(video));
+    }
+    if let Some(animation) = message.animation() {
+        return Some(media_info_from_animation(animation));
+    }
+    if let Some(info) = message.video_note().and_then(media_info_from_video_note) {
+        return Some(info);
+    }
+    if let Some(voice) = message.voice() {
+        return Some(media_info_from_voice(voice));
+    }
+    if let Some(audio) = message.audio() {
+        return Some(media_info_from_audio(audio));
+    }
+    message.document().map(media_info_from_document)
+}
+
+/// Media info of a single size of a photo.
+#[must_use]
+pub fn media_info_from_photo(photo: &PhotoSize) -> MessageMediaInfo<'_> {
+    MessageMediaInfo {
+        width: photo.width,
+        height: photo.height,
+        is_sticker: false,
+        is_gif: false,
+        is_video: false,
+        is_image: true,
+        is_sound: false,
+        is_voice_or_video_note: false,
+        is_vector_sticker: false,
+        kind: MediaKind::Photo,
+        duration: None,
+        mime_type: Some(mime::IMAGE_JPEG),
+        file: &photo.file,
+        thumb: None,
+        thumb_width: None,
+        thumb_height: None,
+    }
+}
+
+/// Media info of a sticker.
+#[must_use]
+pub fn media_info_from_sticker(sticker: &Sticker) -> MessageMediaInfo<'_> {
+    MessageMediaInfo {
+        width: sticker.width.into(),
+        height: sticker.height.into(),
+        is_sticker: true,
+        is_gif: false,
+        is_video: sticker.is_video(),
+        is_sound: false,
+        is_image: !sticker.is_video() && !sticker.is_animated(),
+        is_voice_or_video_note: false,
+        is_vector_sticker: sticker.is_animated(),
+        kind: MediaKind::Sticker,
+        duration: None,
+        mime_type: sticker_mime_type(sticker),
+        file: &sticker.file,
+        thumb: sticker.thumb.as_ref().map(|x| &x.file),
+        thumb_width: sticker.thumb.as_ref().map(|x| x.width),
+        thumb_height: sticker.thumb.as_ref().map(|x| x.height),
+    }
+}
+
+/// Media info of a video.
+#[must_use]
+pub fn media_info_from_video(video: &Video) -> MessageMediaInfo<'_> {
+    MessageMediaInfo {
+        width: video.width,
+        height: video.height,
+        is_sticker: false,
+        is_gif: false,
+        is_video: true,
+        is_image: false,
+        is_sound: false,
+        is_voice_or_video_note: false,
+        is_vector_sticker: false,
+        kind: MediaKind::Video,
+        duration: Some(video.duration),
+        mime_type: video.mime_type.clone(),
+        file: &video.file,
+        thumb: video.thumb.as_ref().map(|x| &x.file),
+        thumb_width: video.thumb.as_ref().map(|x| x.width),
+        thumb_height: video.thumb.as_ref().map(|x| x.height),
+    }
+}
+
+/// Media info of a GIF.
+#[must_use]
+pub fn media_info_from_animation(animation: &Animation) -> MessageMediaInfo<'_> {
+    MessageMediaInfo {
+        width: animation.width,
+        height: animation.height,
+        is_sticker: false,
+        is_video: true,
+        is_gif: true,
+        is_image: false,
+        is_sound: false,
+        is_voice_or_video_note: false,
+        is_vector_sticker: false,
+        kind: MediaKind::Animation,
+        duration: Some(animation.duration),
+        mime_type: animation.mime_type.clone(),
+        file: &animation.file,
+        thumb: animation.thumb.as_ref().map(|x| &x.file),
+        thumb_width: animation.thumb.as_ref().map(|x| x.width),
+        thumb_height: animation.thumb.as_ref().map(|x| x.height),
+    }
+}
+
+/// Media info of a round video. Telegram doesn't say what size they are,
+/// so it's taken from the thumbnail, and without one, this returns `None`.
+#[must_use]
+pub fn media_info_from_video_note(video_note: &VideoNote) -> Option<MessageMediaInfo<'_>> {
+    let thumb = video_note.thumb.as_ref()?;
+    Some(MessageMediaInfo {
+        width: thumb.width,
+        height: thumb.height,
+        is_sticker: false,
+        is_video: true,
+        is_gif: false,
+        is_image: false,
+        is_sound: false,
+        is_voice_or_video_note: true,
+        is_vector_sticker: false,
+        kind: MediaKind::VideoNote,
+        duration: Some(video_note.duration),
+        mime_type: None,
+        file: &video_note.file,
+        thumb: Some(&thumb.file),
+        thumb_width: Some(thumb.width),
+        thumb_height: Some(thumb.height),
+    })
+}
+
+/// Media info of a voice message.
+#[must_use]
+pub fn media_info_from_voice(voice: &Voice) -> MessageMediaInfo<'_> {
+    MessageMediaInfo {
+        width: 0,
+        height: 0,
+        is_sticker: false,
+        is_video: false,
+        is_gif: false,
+        is_image: false,
+        is_sound: true,
+        is_voice_or_video_note: true,
+        is_vector_sticker: false,
+        kind: MediaKind::Voice,
+        duration: Some(voice.duration),
+        mime_type: voice.mime_type.clone(),
+        file: &voice.file,
+        thumb: None,
+        thumb_width: None,
+        thumb_height: None,
+    }
+}
+
+/// Media info of an audio file.
+#[must_use]
+pub fn media_info_from_audio(audio: &Audio) -> MessageMediaInfo<'_> {
+    MessageMediaInfo {
+        width: 0,
+        height: 0,
+        is_sticker: false,
+        is_video: false,
+        is_gif: false,
+        is_image: false,
+        is_sound: true,
+        is_voice_or_video_note: false,
+        is_vector_sticker: false,
+        kind: MediaKind::Audio,
+        duration: Some(audio.duration),
+        mime_type: audio.mime_type.clone(),
+        file: &audio.file,
+        thumb: audio.thumb.as_ref().map(|x| &x.file),
+        thumb_width: audio.thumb.as_ref().map(|x| x.width),
+        thumb_height: audio.thumb.as_ref().map(|x| x.height),
+    }
+}
+
+/// Media info of a file sent as a document. What it is
+/// is guessed from its declared MIME type.
+#[must_use]
+pub fn media_info_from_document(document: &Document) -> MessageMediaInfo<'_> {
+    // Guess what kind of media it is by the declared MIME type.
+    let mime_family = document.mime_type.as_ref().map(|x| x.type_().as_str());
+    let (width, height) = document
+        .thumb
+        .as_ref()
+        .map_or((0, 0), |thumb| (thumb.width, thumb.height));
+    MessageMediaInfo {
+        width,
+        height,
+        is_sticker: false,
+        is_video: mime_family == Some("video"),
+        is_gif: false,
+        is_image: mime_family == Some("image"),
+        is_sound: mime_family == Some("audio"),
+        is_voice_or_video_note: false,
+        is_vector_sticker: false,
+        kind: MediaKind::Document,
+        duration: None,
+        mime_type: document.mime_type.clone(),
+        file: &document.file,
+        thumb: document.thumb.as_ref().map(|x| &x.file),
+        thumb_width: document.thumb.as_ref().map(|x| x.width),
+        thumb_height: document.thumb.as_ref().map(|x| x.height),
+    }
+}
+
+/// Orders photo sizes by area, then by file size.
+fn photo_size_key(photo: &PhotoSize) -> (u64, u32) {
+    (
+        u64::from(photo.width) * u64::from(photo.height),
+        photo.file.size,
+    )
+}
+
+/// Telegram doesn't tell us MIME types of stickers, but they
+/// are always in one of these formats.
+fn sticker_mime_type(sticker: &Sticker) -> Option<Mime> {
+    let mime = if sticker.is_animated() {
+        "application/x-tgsticker"
+    } else if sticker.is_video() {
+        "video/webm"
+    } else {
+        "image/webp"
+    };
+    mime.parse().ok()
 }
 
 pub trait FileStuff {
+    /// Returns `true` if the file was given to us by a local Bot API server,
+    /// which gives absolute paths to files on disk instead of paths
+    /// to download them from. An empty path is never local.
+    ///
+    /// This doesn't check if the file actually exists, see
+    /// [`FileStuff::local_path_exists`] for that.
     fn is_local(&self) -> bool;
+    /// Returns `true` if the file is local and is present on disk.
+    fn local_path_exists(&self) -> bool;
+    /// Returns `true` if the file is local and is inside `base`, like the
+    /// working directory of the local Bot API server. Symlinks and `..`
+    /// are resolved first, so a spoofed path can't point outside of it.
+    /// Returns `false` if either of the paths doesn't exist.
+    fn is_within(&self, base: &Path) -> bool;
+    /// Returns the extension of the file's path, if it has one.
+    fn extension(&self) -> Option<&str>;
+    /// Guesses the MIME type of the file by its extension.
+    fn guess_mime(&self) -> Option<Mime>;
 }
 
-impl FileStuff for teloxide::types::File {
+impl FileStuff for File {
     fn is_local(&self) -> bool {
         std::path::Path::new(&self.path).is_absolute()
     }
+    fn local_path_exists(&self) -> bool {
+        self.is_local() && std::path::Path::new(&self.path).is_file()
+    }
+    fn is_within(&self, base: &Path) -> bool {
+        if !self.is_local() {
+            return false;
+        }
+        let (Ok(path), Ok(base)) = (Path::new(&self.path).canonicalize(), base.canonicalize())
+        else {
+            return false;
+        };
+        path.starts_with(base)
+    }
+    fn extension(&self) -> Option<&str> {
+        std::path::Path::new(&self.path)
+            .extension()
+            .and_then(|x| x.to_str())
+    }
+    fn guess_mime(&self) -> Option<Mime> {
+        mime_guess::from_ext(self.extension()?).first()
+    }
 }
 
-pub trait BotStuff {
-    fn download_file_to_vec(
-        &self,
-        file: &FileMeta,
-        to: &mut Vec<u8>,
-    ) -> impl Future<Output = Result<(), RequestError>> + Send;
+/// Error returned by download methods that can fail for reasons
+/// other than a failed request.
+#[derive(Debug)]
+pub enum DownloadError {
+    /// Talking to Telegram failed.
+    Request(RequestError),
+    /// Reading a file of the local Bot API server from disk failed.
+    LocalRead(std::io::Error),
+    /// The file is bigger than the allowed amount of bytes.
+    TooLarge { max_bytes: usize },
+    /// The download took longer than allowed.
+    TimedOut { timeout: Duration },
+}
 
-    fn download_file_to_temp_or_directly(
-        &self,
-        file: &FileMeta,
-    ) -> impl Future<Output = Result<(PathBuf, Option<NamedTempFile>), RequestError>> + Send;
+impl From<RequestError> for DownloadError {
+    fn from(e: RequestError) -> Self {
+        DownloadError::Request(e)
+    }
+}
 
-    fn typing(&self, to_where: ChatId) -> impl Future<Output = Result<(), RequestError>> + Send;
+impl From<std::io::Error> for DownloadError {
+    fn from(e: std::io::Error) -> Self {
+        DownloadError::LocalRead(e)
+    }
 }
 
-impl BotStuff for Bot {
-    async fn download_file_to_vec(
-        &self,
-        file: &FileMeta,
-        to: &mut Vec<u8>,
-    ) -> Result<(), RequestError> {
-        let file = self.get_file(&file.id).await?;
-        to.reserve_exact(file.size as usize);
-        if file.is_local() {
-            // From local bot API. Just read it as vec lmao
-            let mut file = std::fs::File::open(&file.path)?;
+impl std::fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DownloadError::Request(e) => write!(f, "{e}"),
+            DownloadError::LocalRead(e) => write!(f, "Couldn't read the local file: {e}"),
+            DownloadError::TooLarge { max_bytes } => {
+                write!(f, "The file is bigger than {max_bytes} bytes")
+            }
+            DownloadError::TimedOut { timeout } => {
+                write!(f, "The download took longer than {timeout:?}")
+            }
+        }
+    }
+}
 
-            use std::io::Read;
-            file.read_to_end(to)?;
-        } else {
-            let mut stream = self.download_file_stream(&file.path);
+impl std::error::Error for DownloadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DownloadError::Request(e) => Some(e),
+            DownloadError::LocalRead(e) => Some(e),
+            DownloadError::TooLarge { .. } | DownloadError::TimedOut { .. } => None,
+        }
+    }
+}
 
-            while let Some(bytes) = stream.try_next().await? {
-                to.extend_from_slice(&bytes);
+/// Error returned by [`BotStuff::try_get_file`].
+#[derive(Debug)]
+pub enum GetFileError {
+    /// The file is too big for the Bot API to let bots download it,
+    /// which is over 20MB on the cloud one.
+    TooLarge,
+    /// There's no file with this ID, or the ID is broken.
+    NotFound,
+    /// Some other failure.
+    Other(RequestError),
+}
+
+impl From<RequestError> for GetFileError {
+    fn from(e: RequestError) -> Self {
+        use teloxide::ApiError;
+        match e {
+            RequestError::Api(ApiError::Unknown(ref text)) if text.contains("file is too big") => {
+                GetFileError::TooLarge
             }
+            RequestError::Api(
+                ApiError::WrongFileId | ApiError::FileIdInvalid | ApiError::WrongFileIdOrUrl,
+            ) => GetFileError::NotFound,
+            e => GetFileError::Other(e),
         }
+    }
+}
 
-        Ok(())
+impl std::fmt::Display for GetFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GetFileError::TooLarge => write!(f, "The file is too big to be downloaded by bots"),
+            GetFileError::NotFound => write!(f, "The file wasn't found"),
+            GetFileError::Other(e) => write!(f, "{e}"),
+        }
     }
+}
 
-    async fn download_file_to_temp_or_directly(
-        &self,
-        file: &FileMeta,
-    ) -> Result<(PathBuf, Option<NamedTempFile>), RequestError> {
-        let file = self.get_file(&file.id).await?;
-        if file.is_local() {
-            // If file is local, just return that.
-            Ok((std::path::PathBuf::from(file.path), None))
-        } else {
-            // If the file is remote, make a tempfile and use that.
-            let tempfile = tempfile::NamedTempFile::new()?;
+impl std::error::Error for GetFileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GetFileError::Other(e) => Some(e),
+            GetFileError::TooLarge | GetFileError::NotFound => None,
+        }
+    }
+}
 
-            let reopened = tempfile.reopen()?;
-            let mut tokio_file = tokio::fs::File::from_std(reopened);
-            self.download_file(&file.path, &mut tokio_file).await?;
+/// Appends all chunks of `stream` to `to`, bailing out as soon as
+/// more than `max_bytes` were received in total.
+async fn extend_from_stream_with_limit<B, E>(
+    stream: impl Stream<Item = Result<B, E>>,
+    to: &mut Vec<u8>,
+    max_bytes: usize,
+) -> Result<(), DownloadError>
+where
+    B: AsRef<[u8]>,
+    RequestError: From<E>,
+{
+    let mut stream = std::pin::pin!(stream);
+    let started_at = to.len();
+    while let Some(bytes) = stream.try_next().await.map_err(RequestError::from)? {
+        let bytes = bytes.as_ref();
+        if to.len() - started_at + bytes.len() > max_bytes {
+            to.truncate(started_at);
+            return Err(DownloadError::TooLarge { max_bytes });
+        }
+        to.extend_from_slice(bytes);
+    }
+    Ok(())
+}
 
-            Ok((tempfile.path().to_path_buf(), Some(tempfile)))
+/// Appends all chunks of `stream` to `to`, calling `on_progress` with the
+/// amount of bytes received so far and `total` after each one.
+async fn extend_from_stream_with_progress<B, E>(
+    stream: impl Stream<Item = Result<B, E>>,
+    to: &mut Vec<u8>,
+    total: u64,
+    mut on_progress: impl FnMut(u64, u64),
+) -> Result<(), RequestError>
+where
+    B: AsRef<[u8]>,
+    RequestError: From<E>,
+{
+    let mut stream = std::pin::pin!(stream);
+    let mut received = 0;
+    while let Some(bytes) = stream.try_next().await? {
+        let bytes = bytes.as_ref();
+        received += bytes.len() as u64;
+        to.extend_from_slice(bytes);
+        on_progress(received, total);
+    }
+    Ok(())
+}
+
+/// Writes all chunks of `stream` into `writer` as they arrive.
+async fn write_stream_to<B, E, W>(
+    stream: impl Stream<Item = Result<B, E>>,
+    writer: &mut W,
+) -> Result<(), RequestError>
+where
+    B: AsRef<[u8]>,
+    RequestError: From<E>,
+    W: AsyncWrite + Unpin,
+{
+    let mut stream = std::pin::pin!(stream);
+    while let Some(bytes) = stream.try_next().await? {
+        writer.write_all(bytes.as_ref()).await?;
+    }
+    writer.flush().await?;
+    Ok(())
+}
+
+/// How long [`BotStuff::download_file_to_vec_retry`] waits before the first retry.
+/// It waits twice as long before each next one, up to [`MAX_RETRY_DELAY`].
+pub const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// The longest [`BotStuff::download_file_to_vec_retry`] waits between retries.
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Runs `attempt` until it succeeds or fails with something other than a
+/// network error, retrying at most `retries` times. The delay between
+/// attempts starts at `delay` and doubles each time, up to [`MAX_RETRY_DELAY`].
+async fn retry_on_network_error<T, Fut>(
+    retries: u32,
+    mut delay: Duration,
+    mut attempt: impl FnMut() -> Fut,
+) -> Result<T, RequestError>
+where
+    Fut: Future<Output = Result<T, RequestError>>,
+{
+    let mut retried = 0;
+    loop {
+        match attempt().await {
+            Err(RequestError::Network(e)) if retried < retries => {
+                log::warn!("Download failed, retrying in {delay:?}: {e}");
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
+                retried += 1;
+            }
+            result => return result,
         }
     }
+}
 
-    async fn typing(&self, to_where: ChatId) -> Result<(), RequestError> {
-        self.send_chat_action(to_where, teloxide::types::ChatAction::Typing)
-            .await?;
-        Ok(())
+/// Runs up to `concurrency` of `futures` at once, collecting their outputs
+/// in the same order. Stops at, and returns, the first error.
+async fn try_join_buffered<T, Fut>(
+    futures: impl IntoIterator<Item = Fut>,
+    concurrency: usize,
+) -> Result<Vec<T>, RequestError>
+where
+    Fut: Future<Output = Result<T, RequestError>>,
+{
+    futures::stream::iter(futures)
+        .buffered(concurrency.max(1))
+        .try_collect()
+        .await
+}
+
+/// Collects all chunks of `stream` into one buffer. If there's only one
+/// chunk, it's returned as is, without copying.
+async fn collect_bytes<E>(
+    stream: impl Stream<Item = Result<Bytes, E>>,
+    size_hint: usize,
+) -> Result<Bytes, RequestError>
+where
+    RequestError: From<E>,
+{
+    let mut stream = std::pin::pin!(stream);
+    let Some(first) = stream.try_next().await? else {
+        return Ok(Bytes::new());
+    };
+    let Some(second) = stream.try_next().await? else {
+        return Ok(first);
+    };
+
+    let mut buf = BytesMut::with_capacity(size_hint.max(first.len() + second.len()));
+    buf.extend_from_slice(&first);
+    buf.extend_from_slice(&second);
+    while let Some(bytes) = stream.try_next().await? {
+        buf.extend_from_slice(&bytes);
+    }
+    Ok(buf.freeze())
+}
+
+/// Size of chunks that local files are read in when streaming them.
+const LOCAL_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Reads a file from disk as a stream of chunks of `chunk_size` bytes.
+fn local_file_stream(
+    path: PathBuf,
+    chunk_size: usize,
+) -> impl Stream<Item = Result<Bytes, RequestError>> + Send {
+    futures::stream::once(tokio::fs::File::open(path))
+        .map_ok(move |file| ReaderStream::with_capacity(file, chunk_size))
+        .try_flatten()
+        .map_err(RequestError::from)
+}
+
+/// Streams a resolved file, whether it's local or not.
+fn resolved_file_stream(
+    bot: &Bot,
+    file: File,
+) -> impl Stream<Item = Result<Bytes, RequestError>> + Send + 'static {
+    if file.is_local() {
+        local_file_stream(PathBuf::from(file.path), LOCAL_CHUNK_SIZE).left_stream()
+    } else {
+        bot.download_file_stream(&file.path)
+            .map_err(RequestError::from)
+            .right_stream()
+    }
+}
+
+/// Keeps `permit` for as long as `stream` is around.
+fn holding_permit<S: Stream>(
+    stream: S,
+    permit: Option<OwnedSemaphorePermit>,
+) -> impl Stream<Item = S::Item> {
+    stream.map(move |x| {
+        let _ = &permit;
+        x
+    })
+}
+
+/// Keeps pulling chunks out of `stream` in the background while the ones
+/// already pulled wait to be consumed, up to about `buffer_bytes` of them.
+/// A single chunk bigger than that is still let through, alone.
+fn read_ahead(
+    stream: impl Stream<Item = Result<Bytes, RequestError>> + Send + 'static,
+    buffer_bytes: usize,
+) -> impl Stream<Item = Result<Bytes, RequestError>> + Send {
+    let buffer_bytes = buffer_bytes.clamp(1, u32::MAX as usize) as u32;
+    let semaphore = Arc::new(Semaphore::new(buffer_bytes as usize));
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        let mut stream = std::pin::pin!(stream);
+        while let Some(chunk) = stream.next().await {
+            let len = chunk.as_ref().map_or(0, Bytes::len);
+            let permits = u32::try_from(len)
+                .unwrap_or(u32::MAX)
+                .clamp(1, buffer_bytes);
+            let Ok(permit) = semaphore.clone().acquire_many_owned(permits).await else {
+                break;
+            };
+            if tx.send((chunk, permit)).is_err() {
+                // Nobody's listening anymore.
+                break;
+            }
+        }
+    });
+
+    // The permit is dropped as soon as the chunk is handed out,
+    // which makes room for more.
+    futures::stream::unfold(rx, |mut rx| async move {
+        let (chunk, _permit) = rx.recv().await?;
+        Some((chunk, rx))
+    })
+}
+
+/// Runs `download`, failing with [`DownloadError::TimedOut`]
+/// if it doesn't finish in time.
+async fn with_timeout<T>(
+    timeout: Duration,
+    download: impl Future<Output = Result<T, DownloadError>>,
+) -> Result<T, DownloadError> {
+    match tokio::time::timeout(timeout, download).await {
+        Ok(result) => result,
+        Err(_) => Err(DownloadError::TimedOut { timeout }),
+    }
+}
+
+/// How many bytes to preallocate for downloading `file`. The reported size
+/// can be stale for local files and is 0 when unknown, so it's only trusted
+/// for remote files.
+fn expected_size(file: &File) -> usize {
+    if file.is_local() {
+        0
+    } else {
+        file.size as usize
+    }
+}
+
+/// Fails if Telegram says the file is bigger than `max_bytes`.
+fn check_reported_size(file: &File, max_bytes: usize) -> Result<(), DownloadError> {
+    if file.size as usize > max_bytes {
+        return Err(DownloadError::TooLarge { max_bytes });
+    }
+    Ok(())
+}
+
+/// Something that can resolve a [`FileMeta`] into a [`File`] that can be
+/// downloaded, and has a [`Bot`] to download it with.
+/// [`BotStuff`] is implemented for everything that implements this.
+pub trait FileResolver {
+    fn bot(&self) -> &Bot;
+    fn resolve_file(
+        &self,
+        file: &FileMeta,
+    ) -> impl Future<Output = Result<File, RequestError>> + Send;
+
+    /// Send a request that posts something in `chat`. This is how wrappers
+    /// like [`RateLimitedBot`] throttle what [`BotStuff`] sends.
+    /// By default, the request is just sent.
+    fn send_to_chat<R, T>(
+        &self,
+        chat: ChatId,
+        request: R,
+    ) -> impl Future<Output = Result<T, RequestError>> + Send
+    where
+        R: IntoFuture<Output = Result<T, RequestError>> + Send,
+        R::IntoFuture: Send,
+        T: Send,
+    {
+        let _ = chat;
+        request.into_future()
+    }
+
+    /// Wait until a download can start. The download runs for as long as
+    /// the returned permit is kept. This is how wrappers like [`DownloadPool`]
+    /// limit how many downloads [`BotStuff`] runs at once.
+    /// By default, downloads start right away.
+    fn download_permit(&self) -> impl Future<Output = Option<OwnedSemaphorePermit>> + Send {
+        async { None }
+    }
+}
+
+impl FileResolver for Bot {
+    fn bot(&self) -> &Bot {
+        self
+    }
+    async fn resolve_file(&self, file: &FileMeta) -> Result<File, RequestError> {
+        self.get_file(&file.id).await
+    }
+}
+
+pub trait BotStuff {
+    /// Resolve a file like [`Requester::get_file`] does, but tell apart
+    /// the common reasons for it to fail, like the file being too big for
+    /// bots to download.
+    fn try_get_file(
+        &self,
+        file: &FileMeta,
+    ) -> impl Future<Output = Result<File, GetFileError>> + Send;
+
+    fn download_file_to_vec(
+        &self,
+        file: &FileMeta,
+        to: &mut Vec<u8>,
+    ) -> impl Future<Output = Result<(), RequestError>> + Send;
+
+    /// Like [`BotStuff::download_file_to_vec`], but for a file that was
+    /// already resolved with [`Requester::get_file`], saving a request.
+    fn download_resolved_file_to_vec(
+        &self,
+        file: &File,
+        to: &mut Vec<u8>,
+    ) -> impl Future<Output = Result<(), RequestError>> + Send;
+
+    /// Like [`BotStuff::download_file_to_vec`], but tells apart failing to
+    /// read a local file from failing to talk to Telegram, with
+    /// [`DownloadError::LocalRead`] and [`DownloadError::Request`].
+    fn download_file_to_vec_ext(
+        &self,
+        file: &FileMeta,
+        to: &mut Vec<u8>,
+    ) -> impl Future<Output = Result<(), DownloadError>> + Send;
+
+    /// Like [`BotStuff::download_file_to_vec`], but returns the data as
+    /// [`Bytes`], which avoids copying it around when it arrives in one chunk.
+    fn download_file_to_bytes(
+        &self,
+        file: &FileMeta,
+    ) -> impl Future<Output = Result<Bytes, RequestError>> + Send;
+
+    /// Download a file as a stream of chunks, without keeping all of it
+    /// in memory. Local files are read in chunks of 64KiB.
+    fn download_file_as_stream(
+        &self,
+        file: &FileMeta,
+    ) -> impl Stream<Item = Result<Bytes, RequestError>> + Send;
+
+    /// Download only the first `n` bytes of a file, or all of it if it's
+    /// shorter. The download is dropped as soon as there's enough, so this
+    /// is cheap even for big files. Handy for checking what the file
+    /// really is by its header, see [`crate::media::sniff_content_type`].
+    fn download_file_head(
+        &self,
+        file: &FileMeta,
+        n: usize,
+    ) -> impl Future<Output = Result<Vec<u8>, RequestError>> + Send;
+
+    /// Find out what the file really is by its magic bytes, downloading only
+    /// [`crate::media::SNIFF_LEN`] bytes of it.
+    /// See [`crate::media::sniff_content_type`].
+    fn sniff_content_type(
+        &self,
+        file: &FileMeta,
+    ) -> impl Future<Output = Result<Option<&'static str>, RequestError>> + Send;
+
+    /// SHA-256 hash of the file's contents, to tell apart files by what's
+    /// in them, since the same file can come with different IDs. The file
+    /// is hashed while streaming it, without keeping all of it in memory.
+    fn hash_media(
+        &self,
+        file: &FileMeta,
+    ) -> impl Future<Output = Result<[u8; 32], RequestError>> + Send;
+
+    /// Like [`BotStuff::download_file_as_stream`], but keeps downloading
+    /// in the background while the consumer is busy, holding up to about
+    /// `buffer_bytes` of data it hasn't taken yet. When that much piles up,
+    /// the download waits, so a slow consumer doesn't make memory balloon.
+    fn download_file_as_buffered_stream(
+        &self,
+        file: &FileMeta,
+        buffer_bytes: usize,
+    ) -> impl Stream<Item = Result<Bytes, RequestError>> + Send;
+
+    /// Like [`BotStuff::download_file_to_vec`], but fails with
+    /// [`DownloadError::TooLarge`] if the file is bigger than `max_bytes`.
+    ///
+    /// The size Telegram reports is checked before downloading, but the
+    /// amount of actually received bytes is enforced too, in case the
+    /// reported size is wrong. If the limit is hit, `to` is left as it was.
+    fn download_file_to_vec_with_limit(
+        &self,
+        file: &FileMeta,
+        to: &mut Vec<u8>,
+        max_bytes: usize,
+    ) -> impl Future<Output = Result<(), DownloadError>> + Send;
+
+    /// Like [`BotStuff::download_file_to_vec`], but calls `on_progress` with
+    /// the amount of bytes downloaded so far and the total size of the file
+    /// each time a chunk arrives. Local files are reported once, after reading.
+    fn download_file_to_vec_with_progress(
+        &self,
+        file: &FileMeta,
+        to: &mut Vec<u8>,
+        on_progress: impl FnMut(u64, u64) + Send,
+    ) -> impl Future<Output = Result<(), RequestError>> + Send;
+
+    /// Download a file into `writer`, chunk by chunk, without keeping
+    /// all of it in memory.
+    fn download_file_to_writer<W: AsyncWrite + Unpin + Send>(
+        &self,
+        file: &FileMeta,
+        writer: &mut W,
+    ) -> impl Future<Output = Result<(), RequestError>> + Send;
+
+    /// Like [`BotStuff::download_file_to_vec`], but fails with
+    /// [`DownloadError::TimedOut`] if resolving and downloading the file
+    /// takes longer than `timeout`. On any error, `to` is left untouched.
+    fn download_file_to_vec_timeout(
+        &self,
+        file: &FileMeta,
+        to: &mut Vec<u8>,
+        timeout: Duration,
+    ) -> impl Future<Output = Result<(), DownloadError>> + Send;
+
+    /// Like [`BotStuff::download_file_to_vec`], but retries the whole download
+    /// up to `retries` times if it fails due to a network error, waiting
+    /// longer before each retry, see [`RETRY_DELAY`]. `to` is cleared before
+    /// downloading, so it never contains leftovers of a failed attempt.
+    fn download_file_to_vec_retry(
+        &self,
+        file: &FileMeta,
+        to: &mut Vec<u8>,
+        retries: u32,
+    ) -> impl Future<Output = Result<(), RequestError>> + Send;
+
+    /// Download all `files`, up to `concurrency` of them at once. Data of
+    /// each file is returned in the same order as the files were given.
+    /// If any download fails, the rest are cancelled.
+    fn download_files_to_vecs(
+        &self,
+        files: &[FileMeta],
+        concurrency: usize,
+    ) -> impl Future<Output = Result<Vec<Vec<u8>>, RequestError>> + Send;
+
+    fn download_file_to_temp_or_directly(
+        &self,
+        file: &FileMeta,
+    ) -> impl Future<Output = Result<(PathBuf, Option<NamedTempFile>), RequestError>> + Send;
+
+    /// Find out if a file is already on disk, which is the case with a local
+    /// Bot API server. Returns its path if so, or `None` if it has to be
+    /// downloaded. The path is only valid for as long as the server
+    /// keeps the file around.
+    fn resolve_media_path(
+        &self,
+        file: &FileMeta,
+    ) -> impl Future<Output = Result<Option<PathBuf>, RequestError>> + Send;
+
+    /// Download a file to `dest`, replacing what's there. The file is
+    /// downloaded, or copied if it's local, next to it first, so `dest`
+    /// never has a partial download.
+    ///
+    /// # Errors
+    /// Besides failed requests, errors with [`RequestError::Io`] if the
+    /// directory `dest` is in doesn't exist.
+    fn download_file_to_path(
+        &self,
+        file: &FileMeta,
+        dest: &Path,
+    ) -> impl Future<Output = Result<(), RequestError>> + Send;
+
+    /// Like [`BotStuff::download_file_to_path`], but local files are
+    /// hard-linked instead of copied when they're on the same filesystem.
+    /// `dest` then shares its contents with the Bot API server's copy,
+    /// so don't write to it.
+    fn download_file_to_path_linked(
+        &self,
+        file: &FileMeta,
+        dest: &Path,
+    ) -> impl Future<Output = Result<(), RequestError>> + Send;
+
+    /// Like [`BotStuff::download_file_to_temp_or_directly`], but for a file that
+    /// was already resolved with [`Requester::get_file`], saving a request.
+    fn download_resolved_file_to_temp_or_directly(
+        &self,
+        file: &File,
+    ) -> impl Future<Output = Result<(PathBuf, Option<NamedTempFile>), RequestError>> + Send;
+
+    /// Download the media of a message (or of the message it replies to),
+    /// together with what's known about it. Returns `None` if there's no media.
+    /// See [`MessageStuff::get_media_info`].
+    fn download_media(
+        &self,
+        message: &Message,
+    ) -> impl Future<Output = Result<Option<DownloadedMedia>, RequestError>> + Send;
+
+    /// Download all media of an album, like [`BotStuff::download_media`]
+    /// does for single messages. The album is picked the same way as in
+    /// [`collect_media_group`]. If none of `messages` are in an album,
+    /// media of all of them is downloaded instead, so a single message
+    /// works too. Files are downloaded at once, and returned in order.
+    fn download_album(
+        &self,
+        messages: &[Message],
+    ) -> impl Future<Output = Result<Vec<DownloadedMedia>, RequestError>> + Send;
+
+    /// Send media with the `send_*` method that fits its kind, like
+    /// [`Requester::send_photo`] for [`MediaKind::Photo`]. Stickers and video
+    /// notes can't have captions, so `caption` is ignored for them.
+    fn send_media(
+        &self,
+        chat: ChatId,
+        bytes: Vec<u8>,
+        kind: MediaKind,
+        caption: Option<String>,
+    ) -> impl Future<Output = Result<Message, RequestError>> + Send;
+
+    /// Send a message of any length, split into as many messages as needed,
+    /// in order. Text in `MarkdownV2` or HTML is split without breaking its
+    /// markup, see [`Splitter`]. Legacy Markdown is split like plain text.
+    /// If sending any part fails, the rest aren't sent.
+    ///
+    /// Parts are sent [`CHUNK_DELAY`] apart.
+    fn send_long_message(
+        &self,
+        chat: ChatId,
+        text: &str,
+        parse_mode: Option<ParseMode>,
+    ) -> impl Future<Output = Result<Vec<Message>, RequestError>> + Send;
+
+    /// Like [`BotStuff::send_long_message`], but waits `delay` between parts
+    /// instead. The first part is sent right away.
+    fn send_long_message_with_delay(
+        &self,
+        chat: ChatId,
+        text: &str,
+        parse_mode: Option<ParseMode>,
+        delay: Duration,
+    ) -> impl Future<Output = Result<Vec<Message>, RequestError>> + Send;
+
+    /// Reply to a message with text of any length, split like in
+    /// [`BotStuff::send_long_message`]. Every part replies to `to`, and in
+    /// forums, goes into the same topic. The text is sent as plain text.
+    fn reply_long_message(
+        &self,
+        to: &Message,
+        text: &str,
+    ) -> impl Future<Output = Result<Vec<Message>, RequestError>> + Send;
+
+    /// Send media again by its file ID, without uploading it. The `send_*`
+    /// method is picked by [`MessageMediaInfo::kind`], since a file ID only
+    /// works with the method for the kind of media it was sent as. This means
+    /// stickers of all formats, including video and animated ones, are sent
+    /// with [`Requester::send_sticker`], despite `is_video` being set for
+    /// video stickers. Stickers and video notes can't have captions, so
+    /// `caption` is ignored for them.
+    fn resend_media(
+        &self,
+        chat: ChatId,
+        info: &MessageMediaInfo<'_>,
+        caption: Option<String>,
+    ) -> impl Future<Output = Result<Message, RequestError>> + Send;
+
+    /// Username of the bot, without the `@`, like the one
+    /// [`MessageStuff::parse_command`] wants. It's asked from Telegram
+    /// only the first time, and then cached for the lifetime of the process.
+    fn bot_username(&self) -> impl Future<Output = Result<String, RequestError>> + Send;
+
+    /// Show a chat action, like "uploading video...", in the chat.
+    fn send_action(
+        &self,
+        to_where: ChatId,
+        action: ChatAction,
+    ) -> impl Future<Output = Result<(), RequestError>> + Send;
+
+    fn typing(&self, to_where: ChatId) -> impl Future<Output = Result<(), RequestError>> + Send;
+
+    /// Keep showing "typing..." in the chat until the returned guard is dropped.
+    /// Telegram stops showing it after about 5 seconds, so it's re-sent
+    /// every 4 seconds in a background task, which sends it with a clone
+    /// of this bot.
+    ///
+    /// # Panics
+    /// Panics if called outside of a Tokio runtime.
+    #[must_use = "typing stops as soon as the guard is dropped"]
+    fn typing_guard(&self, to_where: ChatId) -> TypingGuard
+    where
+        Self: Clone + Send + 'static;
+
+    /// Show "typing..." in the chat for as long as `fut` runs, like with
+    /// [`BotStuff::typing_guard`], and return what it returns. Typing
+    /// stops when `fut` finishes, panics, or gets cancelled.
+    ///
+    /// # Panics
+    /// Panics if called outside of a Tokio runtime.
+    fn with_typing<F: Future + Send>(
+        &self,
+        to_where: ChatId,
+        fut: F,
+    ) -> impl Future<Output = F::Output> + Send
+    where
+        Self: Clone + Send + 'static;
+}
+
+/// Media downloaded with [`BotStuff::download_media`].
+#[derive(Clone, Debug)]
+pub struct DownloadedMedia {
+    pub bytes: Vec<u8>,
+    pub kind: MediaKind,
+    /// Dimensions read from the downloaded image if possible,
+    /// otherwise the ones reported by Telegram.
+    pub width: u32,
+    pub height: u32,
+    /// Declared MIME type, or one guessed from the file's extension.
+    pub mime_type: Option<Mime>,
+}
+
+impl DownloadedMedia {
+    fn new(media: &MessageMediaInfo, file: &File, bytes: Vec<u8>) -> Self {
+        let (width, height) =
+            crate::media::image_dimensions(&bytes).unwrap_or((media.width, media.height));
+        DownloadedMedia {
+            kind: media.kind,
+            width,
+            height,
+            mime_type: media.mime_type.clone().or_else(|| file.guess_mime()),
+            bytes,
+        }
+    }
+}
+
+/// Sends the file with the `send_*` method for this kind of media.
+async fn send_input_file(
+    bot: &Bot,
+    chat: ChatId,
+    file: InputFile,
+    kind: MediaKind,
+    caption: Option<String>,
+) -> Result<Message, RequestError> {
+    use teloxide::payloads::setters::*;
+
+    macro_rules! send_with_caption {
+        ($method:ident) => {{
+            let mut request = bot.$method(chat, file);
+            if let Some(caption) = caption {
+                request = request.caption(caption);
+            }
+            request.await
+        }};
+    }
+
+    match kind {
+        MediaKind::Photo => send_with_caption!(send_photo),
+        MediaKind::Video => send_with_caption!(send_video),
+        MediaKind::Animation => send_with_caption!(send_animation),
+        MediaKind::Voice => send_with_caption!(send_voice),
+        MediaKind::Audio => send_with_caption!(send_audio),
+        MediaKind::Document => send_with_caption!(send_document),
+        MediaKind::Sticker => bot.send_sticker(chat, file).await,
+        MediaKind::VideoNote => bot.send_video_note(chat, file).await,
+    }
+}
+
+/// How long [`BotStuff::send_long_message`] and [`BotStuff::reply_long_message`]
+/// wait between parts, so that a lot of them don't trip flood limits.
+pub const CHUNK_DELAY: Duration = Duration::from_millis(50);
+
+/// Does [`BotStuff::download_file_to_vec_retry`], starting with waiting
+/// `delay` before the first retry.
+async fn download_to_vec_retrying<B: FileResolver + Sync>(
+    bot: &B,
+    file: &FileMeta,
+    to: &mut Vec<u8>,
+    retries: u32,
+    delay: Duration,
+) -> Result<(), RequestError> {
+    to.clear();
+    *to = retry_on_network_error(retries, delay, || async move {
+        let mut data = Vec::new();
+        bot.download_file_to_vec(file, &mut data).await?;
+        Ok(data)
+    })
+    .await?;
+    Ok(())
+}
+
+/// Sends each of `chunks` as a message, in order, waiting `delay`
+/// between them, with requests set up by `configure`.
+async fn send_chunks<B: FileResolver + Sync>(
+    bot: &B,
+    chat: ChatId,
+    chunks: Vec<String>,
+    delay: Duration,
+    configure: impl Fn(JsonRequest<SendMessage>) -> JsonRequest<SendMessage> + Send,
+) -> Result<Vec<Message>, RequestError> {
+    let mut sent_messages = Vec::with_capacity(chunks.len());
+    for (i, chunk) in chunks.into_iter().enumerate() {
+        if i > 0 {
+            tokio::time::sleep(delay).await;
+        }
+        let request = configure(bot.bot().send_message(chat, chunk));
+        sent_messages.push(bot.send_to_chat(chat, request).await?);
+    }
+    Ok(sent_messages)
+}
+
+/// Saves `file` to `dest` through a temporary file next to it, which
+/// replaces `dest` once it's complete. If `link` is set, local files are
+/// hard-linked into place when possible instead of copied.
+async fn save_file_to_path<B: FileResolver + Sync>(
+    bot: &B,
+    file: &FileMeta,
+    dest: &Path,
+    link: bool,
+) -> Result<(), RequestError> {
+    let dir = match dest.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if !dir.is_dir() {
+        return Err(RequestError::Io(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("Directory {} doesn't exist", dir.display()),
+        )));
+    }
+
+    let file = bot.resolve_file(file).await?;
+    let _permit = bot.download_permit().await;
+    if file.is_local() {
+        if link {
+            let linked =
+                tempfile::Builder::new().make_in(dir, |path| std::fs::hard_link(&file.path, path));
+            // Fall back to copying, e.g. if they're on different filesystems.
+            if let Ok(linked) = linked {
+                linked.persist(dest).map_err(|e| e.error)?;
+                return Ok(());
+            }
+        }
+        let tempfile = NamedTempFile::new_in(dir)?;
+        tokio::fs::copy(&file.path, tempfile.path()).await?;
+        tempfile.persist(dest).map_err(|e| e.error)?;
+    } else {
+        let tempfile = NamedTempFile::new_in(dir)?;
+        let mut writer = BufWriter::new(tokio::fs::File::from_std(tempfile.reopen()?));
+        bot.bot().download_file(&file.path, &mut writer).await?;
+        writer.flush().await?;
+        tempfile.persist(dest).map_err(|e| e.error)?;
+    }
+    Ok(())
+}
+
+/// Keeps the "typing..." indicator going. See [`BotStuff::typing_guard`].
+pub struct TypingGuard {
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for TypingGuard {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl<T: FileResolver + Sync> BotStuff for T {
+    async fn try_get_file(&self, file: &FileMeta) -> Result<File, GetFileError> {
+        Ok(self.resolve_file(file).await?)
+    }
+
+    async fn download_file_to_vec(
+        &self,
+        file: &FileMeta,
+        to: &mut Vec<u8>,
+    ) -> Result<(), RequestError> {
+        let file = self.resolve_file(file).await?;
+        self.download_resolved_file_to_vec(&file, to).await
+    }
+
+    async fn download_resolved_file_to_vec(
+        &self,
+        file: &File,
+        to: &mut Vec<u8>,
+    ) -> Result<(), RequestError> {
+        let _permit = self.download_permit().await;
+        to.reserve_exact(expected_size(file));
+        if file.is_local() {
+            // From local bot API. Just read it as vec lmao
+            let mut file = std::fs::File::open(&file.path)?;
+
+            use std::io::Read;
+            file.read_to_end(to)?;
+        } else {
+            let mut stream = self.bot().download_file_stream(&file.path);
+
+            while let Some(bytes) = stream.try_next().await? {
+                to.extend_from_slice(&bytes);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn download_file_to_vec_ext(
+        &self,
+        file: &FileMeta,
+        to: &mut Vec<u8>,
+    ) -> Result<(), DownloadError> {
+        let file = self.resolve_file(file).await?;
+        if file.is_local() {
+            let _permit = self.download_permit().await;
+            // Read it asynchronously, so that a timeout can cancel it.
+            use tokio::io::AsyncReadExt;
+            tokio::fs::File::open(&file.path)
+                .await?
+                .read_to_end(to)
+                .await?;
+        } else {
+            self.download_resolved_file_to_vec(&file, to).await?;
+        }
+        Ok(())
+    }
+
+    async fn download_file_to_bytes(&self, file: &FileMeta) -> Result<Bytes, RequestError> {
+        let file = self.resolve_file(file).await?;
+        let _permit = self.download_permit().await;
+        if file.is_local() {
+            let mut buf = BytesMut::with_capacity(expected_size(&file)).writer();
+            let mut file = std::fs::File::open(&file.path)?;
+            std::io::copy(&mut file, &mut buf)?;
+            Ok(buf.into_inner().freeze())
+        } else {
+            let stream = self.bot().download_file_stream(&file.path);
+            collect_bytes(stream, expected_size(&file)).await
+        }
+    }
+
+    fn download_file_as_stream(
+        &self,
+        file: &FileMeta,
+    ) -> impl Stream<Item = Result<Bytes, RequestError>> + Send {
+        let file = file.clone();
+        futures::stream::once(async move {
+            let file = self.resolve_file(&file).await?;
+            let permit = self.download_permit().await;
+            let stream = resolved_file_stream(self.bot(), file);
+            Ok::<_, RequestError>(holding_permit(stream, permit))
+        })
+        .try_flatten()
+    }
+
+    async fn download_file_head(&self, file: &FileMeta, n: usize) -> Result<Vec<u8>, RequestError> {
+        let file = self.resolve_file(file).await?;
+        let _permit = self.download_permit().await;
+        let mut head = Vec::with_capacity(n.min(expected_size(&file)));
+        if file.is_local() {
+            use tokio::io::AsyncReadExt;
+            let local = tokio::fs::File::open(&file.path).await?;
+            local.take(n as u64).read_to_end(&mut head).await?;
+        } else {
+            let mut stream = self.bot().download_file_stream(&file.path);
+            while head.len() < n {
+                let Some(chunk) = stream.try_next().await? else {
+                    break;
+                };
+                let needed = n - head.len();
+                head.extend_from_slice(&chunk[..chunk.len().min(needed)]);
+            }
+        }
+        Ok(head)
+    }
+
+    async fn sniff_content_type(
+        &self,
+        file: &FileMeta,
+    ) -> Result<Option<&'static str>, RequestError> {
+        let head = self
+            .download_file_head(file, crate::media::SNIFF_LEN)
+            .await?;
+        Ok(crate::media::sniff_content_type(&head))
+    }
+
+    async fn hash_media(&self, file: &FileMeta) -> Result<[u8; 32], RequestError> {
+        use sha2::{Digest, Sha256};
+
+        let mut hasher = Sha256::new();
+        let mut stream = std::pin::pin!(self.download_file_as_stream(file));
+        while let Some(chunk) = stream.try_next().await? {
+            hasher.update(&chunk);
+        }
+        Ok(hasher.finalize().into())
+    }
+
+    fn download_file_as_buffered_stream(
+        &self,
+        file: &FileMeta,
+        buffer_bytes: usize,
+    ) -> impl Stream<Item = Result<Bytes, RequestError>> + Send {
+        let file = file.clone();
+        futures::stream::once(async move {
+            let file = self.resolve_file(&file).await?;
+            let permit = self.download_permit().await;
+            let stream = holding_permit(resolved_file_stream(self.bot(), file), permit);
+            Ok::<_, RequestError>(read_ahead(stream, buffer_bytes))
+        })
+        .try_flatten()
+    }
+
+    async fn download_file_to_vec_with_limit(
+        &self,
+        file: &FileMeta,
+        to: &mut Vec<u8>,
+        max_bytes: usize,
+    ) -> Result<(), DownloadError> {
+        let file = self.resolve_file(file).await?;
+        check_reported_size(&file, max_bytes)?;
+        let _permit = self.download_permit().await;
+        to.reserve_exact(expected_size(&file));
+        if file.is_local() {
+            let file = std::fs::File::open(&file.path)?;
+
+            // Read one byte over the limit to know if the file is bigger.
+            use std::io::Read;
+            let read = file
+                .take((max_bytes as u64).saturating_add(1))
+                .read_to_end(to)?;
+            if read > max_bytes {
+                to.truncate(to.len() - read);
+                return Err(DownloadError::TooLarge { max_bytes });
+            }
+        } else {
+            let stream = self.bot().download_file_stream(&file.path);
+            extend_from_stream_with_limit(stream, to, max_bytes).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn download_file_to_vec_with_progress(
+        &self,
+        file: &FileMeta,
+        to: &mut Vec<u8>,
+        mut on_progress: impl FnMut(u64, u64) + Send,
+    ) -> Result<(), RequestError> {
+        let file = self.resolve_file(file).await?;
+        let total = u64::from(file.size);
+        let _permit = self.download_permit().await;
+        to.reserve_exact(expected_size(&file));
+        if file.is_local() {
+            let mut file = std::fs::File::open(&file.path)?;
+
+            use std::io::Read;
+            let read = file.read_to_end(to)?;
+            on_progress(read as u64, total);
+        } else {
+            let stream = self.bot().download_file_stream(&file.path);
+            extend_from_stream_with_progress(stream, to, total, on_progress).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn download_file_to_writer<W: AsyncWrite + Unpin + Send>(
+        &self,
+        file: &FileMeta,
+        writer: &mut W,
+    ) -> Result<(), RequestError> {
+        let file = self.resolve_file(file).await?;
+        let _permit = self.download_permit().await;
+        if file.is_local() {
+            let mut file = tokio::fs::File::open(&file.path).await?;
+            tokio::io::copy(&mut file, writer).await?;
+            writer.flush().await?;
+        } else {
+            let stream = self.bot().download_file_stream(&file.path);
+            write_stream_to(stream, writer).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn download_file_to_vec_timeout(
+        &self,
+        file: &FileMeta,
+        to: &mut Vec<u8>,
+        timeout: Duration,
+    ) -> Result<(), DownloadError> {
+        // Download separately, so that `to` isn't left half-filled on timeout.
+        let data = with_timeout(timeout, async {
+            let mut data = Vec::new();
+            self.download_file_to_vec_ext(file, &mut data).await?;
+            Ok(data)
+        })
+        .await?;
+
+        if to.is_empty() {
+            *to = data;
+        } else {
+            to.extend_from_slice(&data);
+        }
+        Ok(())
+    }
+
+    async fn download_file_to_vec_retry(
+        &self,
+        file: &FileMeta,
+        to: &mut Vec<u8>,
+        retries: u32,
+    ) -> Result<(), RequestError> {
+        download_to_vec_retrying(self, file, to, retries, RETRY_DELAY).await
+    }
+
+    async fn download_files_to_vecs(
+        &self,
+        files: &[FileMeta],
+        concurrency: usize,
+    ) -> Result<Vec<Vec<u8>>, RequestError> {
+        // Collecting first sidesteps a compiler bug with lifetimes of
+        // closures in streams that makes the future not `Send`.
+        let downloads: Vec<_> = files
+            .iter()
+            .map(|file| async move {
+                let mut data = Vec::new();
+                self.download_file_to_vec(file, &mut data).await?;
+                Ok(data)
+            })
+            .collect();
+        try_join_buffered(downloads, concurrency).await
+    }
+
+    async fn download_file_to_temp_or_directly(
+        &self,
+        file: &FileMeta,
+    ) -> Result<(PathBuf, Option<NamedTempFile>), RequestError> {
+        let file = self.resolve_file(file).await?;
+        self.download_resolved_file_to_temp_or_directly(&file).await
+    }
+
+    async fn resolve_media_path(&self, file: &FileMeta) -> Result<Option<PathBuf>, RequestError> {
+        let file = self.resolve_file(file).await?;
+        Ok(file.is_local().then(|| PathBuf::from(file.path)))
+    }
+
+    async fn download_file_to_path(
+        &self,
+        file: &FileMeta,
+        dest: &Path,
+    ) -> Result<(), RequestError> {
+        save_file_to_path(self, file, dest, false).await
+    }
+
+    async fn download_file_to_path_linked(
+        &self,
+        file: &FileMeta,
+        dest: &Path,
+    ) -> Result<(), RequestError> {
+        save_file_to_path(self, file, dest, true).await
+    }
+
+    async fn download_resolved_file_to_temp_or_directly(
+        &self,
+        file: &File,
+    ) -> Result<(PathBuf, Option<NamedTempFile>), RequestError> {
+        if file.is_local() {
+            // If file is local, just return that.
+            Ok((std::path::PathBuf::from(&file.path), None))
+        } else {
+            // If the file is remote, make a tempfile and use that.
+            let _permit = self.download_permit().await;
+            let tempfile = tempfile::NamedTempFile::new()?;
+
+            // Buffer the writes, since chunks of a download can be small.
+            let reopened = tempfile.reopen()?;
+            let mut writer = BufWriter::new(tokio::fs::File::from_std(reopened));
+            self.bot().download_file(&file.path, &mut writer).await?;
+            writer.flush().await?;
+
+            Ok((tempfile.path().to_path_buf(), Some(tempfile)))
+        }
+    }
+
+    async fn download_media(
+        &self,
+        message: &Message,
+    ) -> Result<Option<DownloadedMedia>, RequestError> {
+        let Some(media) = message.get_media_info() else {
+            return Ok(None);
+        };
+        let file = self.resolve_file(media.file).await?;
+        let mut bytes = Vec::new();
+        self.download_resolved_file_to_vec(&file, &mut bytes)
+            .await?;
+        Ok(Some(DownloadedMedia::new(&media, &file, bytes)))
+    }
+
+    async fn download_album(
+        &self,
+        messages: &[Message],
+    ) -> Result<Vec<DownloadedMedia>, RequestError> {
+        let mut media = collect_media_group(messages);
+        if media.is_empty() {
+            media = messages
+                .iter()
+                .filter_map(MessageStuff::get_media_info)
+                .collect();
+        }
+        let count = media.len();
+        let downloads: Vec<_> = media
+            .into_iter()
+            .map(|media| async move {
+                let file = self.resolve_file(media.file).await?;
+                let mut bytes = Vec::new();
+                self.download_resolved_file_to_vec(&file, &mut bytes)
+                    .await?;
+                Ok(DownloadedMedia::new(&media, &file, bytes))
+            })
+            .collect();
+        try_join_buffered(downloads, count).await
+    }
+
+    async fn send_media(
+        &self,
+        chat: ChatId,
+        bytes: Vec<u8>,
+        kind: MediaKind,
+        caption: Option<String>,
+    ) -> Result<Message, RequestError> {
+        let file = InputFile::memory(bytes);
+        self.send_to_chat(chat, send_input_file(self.bot(), chat, file, kind, caption))
+            .await
+    }
+
+    async fn send_long_message(
+        &self,
+        chat: ChatId,
+        text: &str,
+        parse_mode: Option<ParseMode>,
+    ) -> Result<Vec<Message>, RequestError> {
+        self.send_long_message_with_delay(chat, text, parse_mode, CHUNK_DELAY)
+            .await
+    }
+
+    async fn send_long_message_with_delay(
+        &self,
+        chat: ChatId,
+        text: &str,
+        parse_mode: Option<ParseMode>,
+        delay: Duration,
+    ) -> Result<Vec<Message>, RequestError> {
+        let chunks = Splitter::new()
+            .markdown(parse_mode == Some(ParseMode::MarkdownV2))
+            .html(parse_mode == Some(ParseMode::Html))
+            .split(text);
+        send_chunks(self, chat, chunks, delay, |request| match parse_mode {
+            Some(parse_mode) => request.parse_mode(parse_mode),
+            None => request,
+        })
+        .await
+    }
+
+    async fn reply_long_message(
+        &self,
+        to: &Message,
+        text: &str,
+    ) -> Result<Vec<Message>, RequestError> {
+        // Outside of forums, the thread ID is of a thread of replies,
+        // which isn't something messages can be sent to.
+        let is_topic_message = matches!(&to.kind, MessageKind::Common(x) if x.is_topic_message);
+        let topic = to.thread_id.filter(|_| is_topic_message);
+        let chunks = Splitter::new().split(text);
+        send_chunks(self, to.chat.id, chunks, CHUNK_DELAY, |request| {
+            let request = request
+                .reply_to_message_id(to.id)
+                .allow_sending_without_reply(true);
+            match topic {
+                Some(topic) => request.message_thread_id(topic),
+                None => request,
+            }
+        })
+        .await
+    }
+
+    async fn resend_media(
+        &self,
+        chat: ChatId,
+        info: &MessageMediaInfo<'_>,
+        caption: Option<String>,
+    ) -> Result<Message, RequestError> {
+        let file = info.to_input_file();
+        self.send_to_chat(
+            chat,
+            send_input_file(self.bot(), chat, file, info.kind, caption),
+        )
+        .await
+    }
+
+    async fn bot_username(&self) -> Result<String, RequestError> {
+        type Usernames = std::sync::Mutex<std::collections::HashMap<String, String>>;
+        static USERNAMES: std::sync::OnceLock<Usernames> = std::sync::OnceLock::new();
+
+        // Different bots can be used in the same process. They're told apart
+        // by their ID, which the token starts with, to not keep tokens around.
+        let bot = self.bot();
+        let bot_id = bot.token().split(':').next().unwrap_or_default();
+        let key = format!("{}{bot_id}", bot.api_url());
+        let usernames = USERNAMES.get_or_init(Default::default);
+        if let Some(username) = usernames
+            .lock()
+            .expect("Username cache lock got poisoned")
+            .get(&key)
+        {
+            return Ok(username.clone());
+        }
+
+        let username = bot.get_me().await?.user.username.unwrap_or_default();
+        usernames
+            .lock()
+            .expect("Username cache lock got poisoned")
+            .insert(key, username.clone());
+        Ok(username)
+    }
+
+    async fn send_action(&self, to_where: ChatId, action: ChatAction) -> Result<(), RequestError> {
+        let request = self.bot().send_chat_action(to_where, action);
+        self.send_to_chat(to_where, request).await?;
+        Ok(())
+    }
+
+    async fn typing(&self, to_where: ChatId) -> Result<(), RequestError> {
+        self.send_action(to_where, ChatAction::Typing).await
+    }
+
+    fn typing_guard(&self, to_where: ChatId) -> TypingGuard
+    where
+        Self: Clone + Send + 'static,
+    {
+        let bot = self.clone();
+        let task = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(4));
+            loop {
+                interval.tick().await;
+                if let Err(e) = bot.typing(to_where).await {
+                    log::warn!("Failed to send typing action: {e}");
+                }
+            }
+        });
+        TypingGuard { task }
+    }
+
+    async fn with_typing<F: Future + Send>(&self, to_where: ChatId, fut: F) -> F::Output
+    where
+        Self: Clone + Send + 'static,
+    {
+        let _guard = self.typing_guard(to_where);
+        fut.await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Make a message in a private chat, with `extra` fields merged into it.
+    fn message_with(extra: serde_json::Value) -> Message {
+        let mut json = serde_json::json!({
+            "message_id": 1,
+            "date": 0,
+            "chat": { "id": 1, "type": "private", "first_name": "A" },
+            "from": { "id": 1, "is_bot": false, "first_name": "A" },
+        });
+        let serde_json::Value::Object(extra) = extra else {
+            panic!("extra fields must be an object");
+        };
+        json.as_object_mut().unwrap().extend(extra);
+        serde_json::from_value(json).expect("test message should deserialize")
+    }
+
+    fn file_json(id: &str) -> serde_json::Value {
+        serde_json::json!({ "file_id": id, "file_unique_id": id, "file_size": 1000 })
+    }
+
+    fn with_file(id: &str, fields: serde_json::Value) -> serde_json::Value {
+        let mut json = file_json(id);
+        let serde_json::Value::Object(fields) = fields else {
+            panic!("fields must be an object");
+        };
+        json.as_object_mut().unwrap().extend(fields);
+        json
+    }
+
+    #[test]
+    fn voice_duration() {
+        let message = message_with(serde_json::json!({
+            "voice": with_file("voice", serde_json::json!({ "duration": 61, "mime_type": "audio/ogg" })),
+        }));
+        let media = message.get_media_info().unwrap();
+        assert!(media.is_sound);
+        assert_eq!(media.duration, Some(61));
+        assert!(media.require_raster().is_err());
+    }
+
+    #[test]
+    fn video_duration() {
+        let message = message_with(serde_json::json!({
+            "video": with_file("video", serde_json::json!({
+                "width": 1280, "height": 720, "duration": 15, "mime_type": "video/mp4",
+            })),
+        }));
+        let media = message.get_media_info().unwrap();
+        assert!(media.is_video);
+        assert!(media.can_convert_to_gif());
+        assert_eq!(media.duration, Some(15));
+    }
+
+    #[test]
+    fn photo_has_no_duration() {
+        let message = message_with(serde_json::json!({
+            "photo": [with_file("photo", serde_json::json!({ "width": 90, "height": 90 }))],
+        }));
+        let media = message.get_media_info().unwrap();
+        assert!(media.is_image);
+        assert!(!media.can_convert_to_gif());
+        assert_eq!(media.duration, None);
+    }
+
+    #[test]
+    fn audio_is_sound() {
+        let message = message_with(serde_json::json!({
+            "audio": with_file("audio", serde_json::json!({
+                "duration": 200, "mime_type": "audio/mpeg", "title": "song",
+            })),
+        }));
+        let media = message.get_media_info().unwrap();
+        assert!(media.is_sound);
+        assert!(!media.is_voice_or_video_note);
+        assert!(!media.is_raster());
+        assert_eq!((media.width, media.height), (0, 0));
+        assert_eq!(media.duration, Some(200));
+        assert_eq!(media.file.id, "audio");
+    }
+
+    #[test]
+    fn document_media_family() {
+        let document = |mime: &str| {
+            message_with(serde_json::json!({
+                "document": with_file("document", serde_json::json!({
+                    "mime_type": mime,
+                    "thumb": with_file("thumb", serde_json::json!({ "width": 320, "height": 180 })),
+                })),
+            }))
+        };
+
+        let message = document("image/png");
+        let media = message.get_media_info().unwrap();
+        assert!(media.is_image && !media.is_video && !media.is_sound);
+        assert_eq!((media.width, media.height), (320, 180));
+        assert_eq!(media.file.id, "document");
+        assert_eq!(
+            (media.thumb_width, media.thumb_height),
+            (Some(320), Some(180))
+        );
+
+        let message = document("video/mp4");
+        let media = message.get_media_info().unwrap();
+        assert!(media.is_video && !media.is_image && !media.is_sound);
+
+        let message = document("audio/flac");
+        let media = message.get_media_info().unwrap();
+        assert!(media.is_sound && !media.is_image && !media.is_video);
+
+        let message = document("application/pdf");
+        let media = message.get_media_info().unwrap();
+        assert!(!media.is_image && !media.is_video && !media.is_sound);
+        assert!(!media.is_raster());
+        assert!(!media.is_image());
+    }
+
+    #[test]
+    fn document_without_thumb() {
+        let message = message_with(serde_json::json!({
+            "document": with_file("document", serde_json::json!({ "mime_type": "image/jpeg" })),
+        }));
+        let media = message.get_media_info().unwrap();
+        assert_eq!((media.width, media.height), (0, 0));
+    }
+
+    #[test]
+    fn mime_types() {
+        let message = message_with(serde_json::json!({
+            "voice": with_file("voice", serde_json::json!({ "duration": 5, "mime_type": "audio/ogg" })),
+        }));
+        let media = message.get_media_info().unwrap();
+        assert_eq!(media.mime_type.unwrap().essence_str(), "audio/ogg");
+
+        let message = message_with(serde_json::json!({
+            "photo": [with_file("photo", serde_json::json!({ "width": 90, "height": 90 }))],
+        }));
+        let media = message.get_media_info().unwrap();
+        assert_eq!(media.mime_type, Some(mime::IMAGE_JPEG));
+
+        let message = message_with(serde_json::json!({
+            "sticker": with_file("sticker", serde_json::json!({
+                "width": 512, "height": 512, "type": "regular",
+                "is_animated": false, "is_video": false,
+            })),
+        }));
+        let media = message.get_media_info().unwrap();
+        assert_eq!(media.mime_type.unwrap().essence_str(), "image/webp");
+    }
+
+    #[test]
+    fn thumbnails() {
+        let message = message_with(serde_json::json!({
+            "video": with_file("video", serde_json::json!({
+                "width": 1280, "height": 720, "duration": 15, "mime_type": "video/mp4",
+                "thumb": with_file("thumb", serde_json::json!({ "width": 320, "height": 180 })),
+            })),
+        }));
+        let media = message.get_media_info().unwrap();
+        assert_eq!(media.thumb.unwrap().id, "thumb");
+        assert_eq!(
+            (media.thumb_width, media.thumb_height),
+            (Some(320), Some(180))
+        );
+
+        let message = message_with(serde_json::json!({
+            "photo": [with_file("photo", serde_json::json!({ "width": 90, "height": 90 }))],
+        }));
+        let media = message.get_media_info().unwrap();
+        assert!(media.thumb.is_none());
+        assert_eq!((media.thumb_width, media.thumb_height), (None, None));
+    }
+
+    #[test]
+    fn orientation() {
+        let message = message_with(serde_json::json!({
+            "sticker": with_file("sticker", serde_json::json!({
+                "width": 512, "height": 512, "type": "regular",
+                "is_animated": false, "is_video": false,
+            })),
+        }));
+        let media = message.get_media_info().unwrap();
+        assert_eq!(media.aspect_ratio(), Some(1.0));
+        assert!(media.is_square() && !media.is_portrait() && !media.is_landscape());
+
+        let message = message_with(serde_json::json!({
+            "video": with_file("video", serde_json::json!({
+                "width": 1280, "height": 720, "duration": 15, "mime_type": "video/mp4",
+            })),
+        }));
+        let media = message.get_media_info().unwrap();
+        assert_eq!(media.aspect_ratio(), Some(1280.0 / 720.0));
+        assert!(media.is_landscape() && !media.is_portrait() && !media.is_square());
+
+        let message = message_with(serde_json::json!({
+            "voice": with_file("voice", serde_json::json!({ "duration": 5, "mime_type": "audio/ogg" })),
+        }));
+        let media = message.get_media_info().unwrap();
+        assert_eq!(media.aspect_ratio(), None);
+        assert!(!media.is_square() && !media.is_portrait() && !media.is_landscape());
+    }
+
+    #[test]
+    fn sticker_kinds() {
+        let sticker = |is_animated: bool, is_video: bool| {
+            message_with(serde_json::json!({
+                "sticker": with_file("sticker", serde_json::json!({
+                    "width": 512, "height": 512, "type": "regular",
+                    "is_animated": is_animated, "is_video": is_video,
+                })),
+            }))
+        };
+        let kind = |message: &Message| message.get_media_info().unwrap().sticker_kind();
+
+        assert_eq!(kind(&sticker(false, false)), Some(StickerKind::Static));
+        assert_eq!(kind(&sticker(true, false)), Some(StickerKind::Animated));
+        assert_eq!(kind(&sticker(false, true)), Some(StickerKind::Video));
+
+        // Vector stickers are still returned, but aren't raster.
+        let is_raster = |message: &Message| {
+            let media = message.get_media_info().unwrap();
+            media.require_raster().is_ok()
+        };
+        assert!(is_raster(&sticker(false, false)));
+        assert!(!is_raster(&sticker(true, false)));
+        assert!(is_raster(&sticker(false, true)));
+
+        let message = message_with(serde_json::json!({
+            "photo": [with_file("photo", serde_json::json!({ "width": 90, "height": 90 }))],
+        }));
+        assert_eq!(kind(&message), None);
+
+        let extension = |message: &Message| {
+            let media = message.get_media_info().unwrap();
+            media.sticker_file_extension()
+        };
+        assert_eq!(extension(&sticker(false, false)), Some("webp"));
+        assert_eq!(extension(&sticker(true, false)), Some("tgs"));
+        assert_eq!(extension(&sticker(false, true)), Some("webm"));
+        assert_eq!(extension(&message), None);
+    }
+
+    #[test]
+    fn sticker_meta() {
+        let message = message_with(serde_json::json!({
+            "sticker": with_file("sticker", serde_json::json!({
+                "width": 512, "height": 512, "type": "regular",
+                "is_animated": false, "is_video": false,
+                "emoji": "🦀", "set_name": "crabs",
+            })),
+        }));
+        let meta = message.sticker_meta().unwrap();
+        assert_eq!(meta.emoji, Some("🦀"));
+        assert_eq!(meta.set_name, Some("crabs"));
+        assert!(!meta.is_premium);
+
+        let message = message_with(serde_json::json!({ "text": "no sticker here" }));
+        assert_eq!(message.sticker_meta(), None);
+    }
+
+    #[test]
+    fn smallest_and_biggest_photo() {
+        let message = message_with(serde_json::json!({
+            "photo": [
+                with_file("medium", serde_json::json!({ "width": 320, "height": 240 })),
+                with_file("small", serde_json::json!({ "width": 90, "height": 67 })),
+                with_file("big", serde_json::json!({ "width": 1280, "height": 960 })),
+            ],
+        }));
+        assert_eq!(message.find_smallest_photo().unwrap().file.id, "small");
+        assert_eq!(message.find_biggest_photo().unwrap().file.id, "big");
+        assert_eq!(message.get_all_photos().unwrap().len(), 3);
+
+        // Wide sizes used to win just by having a bigger width + height.
+        let message = message_with(serde_json::json!({
+            "photo": [
+                with_file("wide", serde_json::json!({ "width": 800, "height": 200 })),
+                with_file("square", serde_json::json!({ "width": 500, "height": 500 })),
+                with_file("tall", serde_json::json!({ "width": 300, "height": 600 })),
+            ],
+        }));
+        assert_eq!(message.find_smallest_photo().unwrap().file.id, "wide");
+        assert_eq!(message.find_biggest_photo().unwrap().file.id, "square");
+
+        let mut heavy = with_file("heavy", serde_json::json!({ "width": 200, "height": 800 }));
+        heavy["file_size"] = 5000.into();
+        let message = message_with(serde_json::json!({
+            "photo": [
+                heavy,
+                with_file("light", serde_json::json!({ "width": 800, "height": 200 })),
+            ],
+        }));
+        assert_eq!(message.find_smallest_photo().unwrap().file.id, "light");
+        assert_eq!(message.find_biggest_photo().unwrap().file.id, "heavy");
+
+        let message = message_with(serde_json::json!({ "text": "no photo here" }));
+        assert!(message.find_smallest_photo().is_none());
+        assert!(message.get_all_photos().is_none());
+    }
+
+    #[test]
+    fn best_photo_under_budget() {
+        let size = |id: &str, side: u32, file_size: u32| {
+            let mut json = with_file(id, serde_json::json!({ "width": side, "height": side }));
+            json["file_size"] = file_size.into();
+            json
+        };
+        let message = message_with(serde_json::json!({
+            "photo": [size("small", 90, 1000), size("medium", 320, 20_000), size("big", 1280, 200_000)],
+        }));
+
+        let best = |max_bytes| message.best_photo_under(max_bytes).unwrap().file.id.clone();
+        assert_eq!(best(1_000_000), "big");
+        assert_eq!(best(200_000), "big");
+        assert_eq!(best(50_000), "medium");
+        assert_eq!(best(1000), "small");
+        // Nothing fits, so the smallest one it is.
+        assert_eq!(best(10), "small");
+
+        let message = message_with(serde_json::json!({ "text": "no photo here" }));
+        assert!(message.best_photo_under(1000).is_none());
+    }
+
+    #[test]
+    fn media_group() {
+        let photo = |id: &str, group: Option<&str>| {
+            message_with(serde_json::json!({
+                "photo": [with_file(id, serde_json::json!({ "width": 90, "height": 90 }))],
+                "media_group_id": group,
+            }))
+        };
+        let messages = [
+            photo("lonely", None),
+            photo("first", Some("album")),
+            photo("other", Some("another album")),
+            photo("second", Some("album")),
+        ];
+        assert_eq!(MessageStuff::media_group_id(&messages[1]), Some("album"));
+
+        let ids: Vec<_> = collect_media_group(&messages)
+            .iter()
+            .map(|x| x.file.id.as_str())
+            .collect();
+        assert_eq!(ids, ["first", "second"]);
+
+        assert!(collect_media_group(&messages[..1]).is_empty());
+    }
+
+    #[test]
+    fn text_with_entities() {
+        let message = message_with(serde_json::json!({
+            "text": "hi there",
+            "entities": [{ "type": "bold", "offset": 0, "length": 2 }],
+        }));
+        let (text, entities) = message.text_full_with_entities().unwrap();
+        assert_eq!(text, "hi there");
+        assert_eq!(entities.len(), 1);
+
+        let message = message_with(serde_json::json!({
+            "photo": [with_file("photo", serde_json::json!({ "width": 90, "height": 90 }))],
+            "caption": "look at this",
+            "caption_entities": [
+                { "type": "italic", "offset": 0, "length": 4 },
+                { "type": "bold", "offset": 8, "length": 4 },
+            ],
+        }));
+        let (text, entities) = message.text_full_with_entities().unwrap();
+        assert_eq!(text, "look at this");
+        assert_eq!(entities.len(), 2);
+
+        let message = message_with(serde_json::json!({
+            "photo": [with_file("photo", serde_json::json!({ "width": 90, "height": 90 }))],
+        }));
+        assert!(message.text_full_with_entities().is_none());
+    }
+
+    #[test]
+    fn urls() {
+        // The emoji is 2 UTF-16 code units long but 4 bytes long,
+        // so byte offsets would slice the text wrong.
+        let message = message_with(serde_json::json!({
+            "text": "🦀 https://example.com and this",
+            "entities": [
+                { "type": "url", "offset": 3, "length": 19 },
+                { "type": "text_link", "offset": 27, "length": 4, "url": "https://example.org/" },
+            ],
+        }));
+        assert_eq!(
+            message.extract_urls(),
+            ["https://example.com", "https://example.org/"]
+        );
+
+        let message = message_with(serde_json::json!({ "text": "no links" }));
+        assert!(message.extract_urls().is_empty());
+    }
+
+    #[test]
+    fn mentions_and_hashtags() {
+        let message = message_with(serde_json::json!({
+            "text": "🦀🦀 @crab and 🦀 Bob #crabs #rust",
+            "entities": [
+                { "type": "mention", "offset": 5, "length": 5 },
+                {
+                    "type": "text_mention", "offset": 18, "length": 3,
+                    "user": { "id": 42, "is_bot": false, "first_name": "Bob" },
+                },
+                { "type": "hashtag", "offset": 22, "length": 6 },
+                { "type": "hashtag", "offset": 29, "length": 5 },
+            ],
+        }));
+        assert_eq!(
+            message.extract_mentions(),
+            [
+                Mention {
+                    username: Some("crab".to_string()),
+                    user: None,
+                },
+                Mention {
+                    username: None,
+                    user: Some(UserId(42)),
+                },
+            ]
+        );
+        assert_eq!(message.extract_hashtags(), ["#crabs", "#rust"]);
+
+        let message = message_with(serde_json::json!({ "text": "nobody" }));
+        assert!(message.extract_mentions().is_empty());
+        assert!(message.extract_hashtags().is_empty());
+    }
+
+    #[test]
+    fn commands() {
+        let command = |text: &str| {
+            let len = text.split_whitespace().next().unwrap().len();
+            message_with(serde_json::json!({
+                "text": text,
+                "entities": [{ "type": "bot_command", "offset": 0, "length": len }],
+            }))
+        };
+
+        let message = command("/ban@mybot 123 spam");
+        assert_eq!(
+            message.parse_command("mybot"),
+            Some(ParsedCommand {
+                command: "ban",
+                target: Some("mybot"),
+                args: "123 spam",
+            })
+        );
+        assert_eq!(message.parse_command("@MyBot").unwrap().command, "ban");
+        assert_eq!(message.parse_command("otherbot"), None);
+
+        let message = command("/start");
+        assert_eq!(
+            message.parse_command("mybot"),
+            Some(ParsedCommand {
+                command: "start",
+                target: None,
+                args: "",
+            })
+        );
+
+        // Not at the start of the message.
+        let message = message_with(serde_json::json!({
+            "text": "try /start",
+            "entities": [{ "type": "bot_command", "offset": 4, "length": 6 }],
+        }));
+        assert_eq!(message.parse_command("mybot"), None);
+
+        // "🖼" is 2 UTF-16 code units long, and comes before the command
+        // in the args, which shouldn't matter.
+        let photo = message_with(serde_json::json!({
+            "photo": [with_file("photo", serde_json::json!({ "width": 1, "height": 1 }))],
+            "caption": "/sticker@mybot 🖼 make it",
+            "caption_entities": [{ "type": "bot_command", "offset": 0, "length": 14 }],
+        }));
+        assert_eq!(
+            photo.parse_command("mybot"),
+            Some(ParsedCommand {
+                command: "sticker",
+                target: Some("mybot"),
+                args: "🖼 make it",
+            })
+        );
+
+        // The command's offset in the caption is counted in UTF-16 too.
+        let photo = message_with(serde_json::json!({
+            "photo": [with_file("photo", serde_json::json!({ "width": 1, "height": 1 }))],
+            "caption": "🖼 /sticker",
+            "caption_entities": [{ "type": "bot_command", "offset": 3, "length": 8 }],
+        }));
+        assert_eq!(photo.parse_command("mybot"), None);
+
+        let message = command("/echo   hello world\n");
+        assert_eq!(message.command_args_raw("mybot"), Some("hello world\n"));
+        assert_eq!(message.parse_command("mybot").unwrap().args, "hello world");
+        let message = command("/echo@otherbot hello");
+        assert_eq!(message.command_args_raw("mybot"), None);
+        let message = message_with(serde_json::json!({ "text": "hello" }));
+        assert_eq!(message.command_args_raw("mybot"), None);
+    }
+
+    #[test]
+    fn permalinks() {
+        let message_in = |chat: serde_json::Value| {
+            let mut message = message_with(serde_json::json!({ "text": "hi" }));
+            message.id = teloxide::types::MessageId(123);
+            message.chat = serde_json::from_value(chat).unwrap();
+            message
+        };
+
+        let message = message_in(serde_json::json!({
+            "id": -1001234567890_i64, "type": "channel", "title": "A", "username": "somechannel",
+        }));
+        assert_eq!(
+            message.permalink().as_deref(),
+            Some("https://t.me/somechannel/123")
+        );
+
+        let message = message_in(serde_json::json!({
+            "id": -1001234567890_i64, "type": "supergroup", "title": "A",
+        }));
+        assert_eq!(
+            message.permalink().as_deref(),
+            Some("https://t.me/c/1234567890/123")
+        );
+
+        let message = message_in(serde_json::json!({
+            "id": 1, "type": "private", "first_name": "A",
+        }));
+        assert_eq!(message.permalink(), None);
+    }
+
+    #[test]
+    fn forward_origins() {
+        let forwarded = |from: serde_json::Value| {
+            let mut json = serde_json::json!({ "text": "hi", "forward_date": 0 });
+            let serde_json::Value::Object(from) = from else {
+                unreachable!();
+            };
+            json.as_object_mut().unwrap().extend(from);
+            message_with(json)
+        };
+
+        let message = message_with(serde_json::json!({ "text": "hi" }));
+        assert!(!message.is_forwarded());
+        assert_eq!(message.forward_origin(), None);
+
+        let message = forwarded(serde_json::json!({
+            "forward_from": { "id": 42, "is_bot": false, "first_name": "Bob" },
+        }));
+        assert!(message.is_forwarded());
+        assert!(matches!(
+            message.forward_origin(),
+            Some(ForwardOrigin::User(user)) if user.id == UserId(42)
+        ));
+
+        let message = forwarded(serde_json::json!({ "forward_sender_name": "Hidden Bob" }));
+        assert_eq!(
+            message.forward_origin(),
+            Some(ForwardOrigin::HiddenUser("Hidden Bob"))
+        );
+
+        let message = forwarded(serde_json::json!({
+            "forward_from_chat": { "id": -1001, "type": "channel", "title": "News" },
+            "forward_from_message_id": 7,
+        }));
+        assert!(matches!(
+            message.forward_origin(),
+            Some(ForwardOrigin::Channel {
+                message_id: Some(MessageId(7)),
+                ..
+            })
+        ));
+
+        let message = forwarded(serde_json::json!({
+            "forward_from_chat": { "id": -1002, "type": "supergroup", "title": "Chat" },
+        }));
+        assert!(matches!(
+            message.forward_origin(),
+            Some(ForwardOrigin::Chat { .. })
+        ));
+    }
+
+    #[test]
+    fn forwarded_media() {
+        let photo = serde_json::json!([with_file(
+            "photo",
+            serde_json::json!({ "width": 90, "height": 90 })
+        ),]);
+        let message = message_with(serde_json::json!({
+            "photo": photo,
+            "forward_sender_name": "Hidden Bob",
+            "forward_date": 0,
+        }));
+        let (media, origin) = message.forwarded_media().unwrap();
+        assert_eq!(media.file.id, "photo");
+        assert_eq!(origin, ForwardOrigin::HiddenUser("Hidden Bob"));
+
+        let not_forwarded = message_with(serde_json::json!({ "photo": photo }));
+        assert!(not_forwarded.forwarded_media().is_none());
+
+        let no_media = message_with(serde_json::json!({
+            "text": "hi",
+            "forward_sender_name": "Hidden Bob",
+            "forward_date": 0,
+        }));
+        assert!(no_media.forwarded_media().is_none());
+
+        // The replied to media wasn't forwarded.
+        let reply = message_with(serde_json::json!({
+            "message_id": 2,
+            "text": "hi",
+            "forward_sender_name": "Hidden Bob",
+            "forward_date": 0,
+            "reply_to_message": serde_json::to_value(&not_forwarded).unwrap(),
+        }));
+        assert!(reply.get_media_info().is_some());
+        assert!(reply.forwarded_media().is_none());
+    }
+
+    #[test]
+    fn reply_chains() {
+        let mut message = message_with(serde_json::json!({ "text": "0" }));
+        assert_eq!(message.reply_chain().count(), 0);
+        assert_eq!(message.root_message().text(), Some("0"));
+
+        for i in 1..=3 {
+            message = message_with(serde_json::json!({
+                "message_id": i + 1,
+                "text": i.to_string(),
+                "reply_to_message": serde_json::to_value(&message).unwrap(),
+            }));
+        }
+        let texts: Vec<_> = message.reply_chain().filter_map(Message::text).collect();
+        assert_eq!(texts, ["2", "1", "0"]);
+        assert_eq!(message.root_message().text(), Some("0"));
+
+        // A message that replies to itself, with no media anywhere.
+        let mut message = message_with(serde_json::json!({ "message_id": 5, "text": "me" }));
+        for _ in 0..3 {
+            message = message_with(serde_json::json!({
+                "message_id": 5,
+                "text": "me",
+                "reply_to_message": serde_json::to_value(&message).unwrap(),
+            }));
+        }
+        assert_eq!(message.reply_chain().count(), 0);
+        assert_eq!(message.root_message().id, MessageId(5));
+        assert!(!message.has_media());
+        assert!(message.get_media_info().is_none());
+        assert!(message.sticker_meta().is_none());
+
+        // Too deep to follow all the way.
+        // Nested by hand, since deserializing that deep overflows the stack.
+        let mut message = message_with(serde_json::json!({ "text": "0" }));
+        for i in 1..=100 {
+            let mut reply = message_with(serde_json::json!({ "message_id": i + 1, "text": "hi" }));
+            let MessageKind::Common(common) = &mut reply.kind else {
+                unreachable!();
+            };
+            common.reply_to_message = Some(Box::new(message));
+            message = reply;
+        }
+        assert_eq!(message.reply_chain().count(), 64);
+    }
+
+    #[test]
+    fn effective_senders() {
+        let message = message_with(serde_json::json!({ "text": "hi" }));
+        let EffectiveSender::User(user) = message.effective_sender() else {
+            panic!("expected a user");
+        };
+        assert_eq!(user.id, UserId(1));
+        assert_eq!(message.effective_chat().id, ChatId(1));
+
+        let group = serde_json::json!({ "id": -1002, "type": "supergroup", "title": "Group" });
+        let anonymous_admin = message_with(serde_json::json!({
+            "text": "hi",
+            "chat": group,
+            "from": {
+                "id": 1_087_968_824_u64,
+                "is_bot": true,
+                "first_name": "Group",
+                "username": "GroupAnonymousBot",
+            },
+            "sender_chat": group,
+        }));
+        let EffectiveSender::Chat(chat) = anonymous_admin.effective_sender() else {
+            panic!("expected a chat");
+        };
+        assert_eq!(chat.id, ChatId(-1002));
+        assert_eq!(anonymous_admin.effective_chat().id, ChatId(-1002));
+
+        let channel = serde_json::json!({ "id": -1001, "type": "channel", "title": "News" });
+        let post = message_with(serde_json::json!({
+            "text": "hi",
+            "chat": channel,
+            "from": null,
+            "sender_chat": channel,
+        }));
+        let EffectiveSender::Chat(chat) = post.effective_sender() else {
+            panic!("expected a chat");
+        };
+        assert_eq!(chat.id, ChatId(-1001));
+        assert_eq!(post.effective_chat().id, ChatId(-1001));
+
+        let message = message_with(serde_json::json!({ "text": "hi", "from": null }));
+        assert_eq!(message.effective_sender(), EffectiveSender::None);
+    }
+
+    #[test]
+    fn sender_display_names() {
+        let message = message_with(serde_json::json!({ "text": "hi" }));
+        assert_eq!(message.sender_display_name().as_deref(), Some("A"));
+
+        let message = message_with(serde_json::json!({
+            "text": "hi",
+            "from": { "id": 1, "is_bot": false, "first_name": "", "username": "crab" },
+        }));
+        assert_eq!(message.sender_display_name().as_deref(), Some("@crab"));
+
+        let post = |signature: Option<&str>| {
+            message_with(serde_json::json!({
+                "text": "hi",
+                "from": null,
+                "author_signature": signature,
+                "sender_chat": { "id": -1001, "type": "channel", "title": "News" },
+            }))
+        };
+        assert_eq!(
+            post(Some("Editor")).sender_display_name().as_deref(),
+            Some("Editor")
+        );
+        assert_eq!(post(None).sender_display_name().as_deref(), Some("News"));
+
+        let message = message_with(serde_json::json!({ "text": "hi", "from": null }));
+        assert_eq!(message.sender_display_name(), None);
+    }
+
+    #[test]
+    fn reported_size_over_limit() {
+        let file: File = serde_json::from_value(with_file(
+            "file",
+            serde_json::json!({ "file_path": "documents/file_1.bin" }),
+        ))
+        .unwrap();
+        assert!(check_reported_size(&file, 1000).is_ok());
+        assert!(matches!(
+            check_reported_size(&file, 999),
+            Err(DownloadError::TooLarge { max_bytes: 999 })
+        ));
+    }
+
+    #[tokio::test]
+    async fn stream_over_limit() {
+        let chunks = || {
+            futures::stream::iter(
+                [vec![1; 400], vec![2; 400], vec![3; 400]].map(Ok::<_, RequestError>),
+            )
+        };
+
+        let mut to = vec![0];
+        extend_from_stream_with_limit(chunks(), &mut to, 1200)
+            .await
+            .unwrap();
+        assert_eq!(to.len(), 1201);
+
+        // The reported size may be a lie, but received data is checked too.
+        let mut to = vec![0];
+        let result = extend_from_stream_with_limit(chunks(), &mut to, 1000).await;
+        assert!(matches!(
+            result,
+            Err(DownloadError::TooLarge { max_bytes: 1000 })
+        ));
+        assert_eq!(to, [0]);
+    }
+
+    #[tokio::test]
+    async fn limited_download_rejects_big_files() {
+        use crate::test_utils::{ok, MockServer};
+        use std::io::Write;
+
+        let mut local = tempfile::NamedTempFile::new().unwrap();
+        local.write_all(&[7; 2000]).unwrap();
+        let local_path = local.path().to_str().unwrap().to_string();
+        let server = MockServer::new(move |request| {
+            let (size, path) = if request.body.contains("big") {
+                (5000, "documents/big.bin")
+            } else if request.body.contains("liar") {
+                (3, "documents/liar.bin")
+            } else if request.body.contains("local") {
+                (3, local_path.as_str())
+            } else {
+                return serde_json::json!("x".repeat(2000));
+            };
+            ok(serde_json::json!({
+                "file_id": "id",
+                "file_unique_id": "id",
+                "file_size": size,
+                "file_path": path,
+            }))
+        })
+        .await;
+        let file = |id| serde_json::from_value(file_json(id)).unwrap();
+
+        // Too big by what Telegram says, so it's not even downloaded.
+        let mut data = vec![0];
+        let result = server
+            .bot
+            .download_file_to_vec_with_limit(&file("big"), &mut data, 1000)
+            .await;
+        assert!(matches!(
+            result,
+            Err(DownloadError::TooLarge { max_bytes: 1000 })
+        ));
+        assert_eq!(data, [0]);
+        assert_eq!(server.methods(), ["GetFile"]);
+
+        // Telegram says it's small, but it turns out bigger.
+        let result = server
+            .bot
+            .download_file_to_vec_with_limit(&file("liar"), &mut data, 1000)
+            .await;
+        assert!(matches!(result, Err(DownloadError::TooLarge { .. })));
+        assert_eq!(data, [0]);
+        assert_eq!(server.methods(), ["GetFile", "GetFile", "liar.bin"]);
+
+        // The same goes for local files.
+        let result = server
+            .bot
+            .download_file_to_vec_with_limit(&file("local"), &mut data, 1000)
+            .await;
+        assert!(matches!(result, Err(DownloadError::TooLarge { .. })));
+        assert_eq!(data, [0]);
+        server
+            .bot
+            .download_file_to_vec_with_limit(&file("local"), &mut data, 2000)
+            .await
+            .unwrap();
+        assert_eq!(data.len(), 2001);
+    }
+
+    #[tokio::test]
+    async fn stream_progress() {
+        let chunks = futures::stream::iter(
+            [vec![1; 100], vec![2; 200], vec![3; 300]].map(Ok::<_, RequestError>),
+        );
+        let mut to = Vec::new();
+        let mut reports = Vec::new();
+        extend_from_stream_with_progress(chunks, &mut to, 600, |done, total| {
+            reports.push((done, total))
+        })
+        .await
+        .unwrap();
+        assert_eq!(to.len(), 600);
+        assert_eq!(reports, [(100, 600), (300, 600), (600, 600)]);
+    }
+
+    #[tokio::test]
+    async fn stream_to_writer() {
+        let chunks = futures::stream::iter(
+            [b"hello ".to_vec(), b"world".to_vec()].map(Ok::<_, RequestError>),
+        );
+        let mut writer = Vec::new();
+        write_stream_to(chunks, &mut writer).await.unwrap();
+        assert_eq!(writer, b"hello world");
+    }
+
+    fn network_error() -> RequestError {
+        // Can't make a `reqwest::Error` directly, so make one fail to build.
+        let error = reqwest::Client::new().get("http://").build().unwrap_err();
+        RequestError::Network(error)
+    }
+
+    #[tokio::test]
+    async fn retries_network_errors() {
+        let mut attempts = 0;
+        let result = retry_on_network_error(3, Duration::from_millis(1), || {
+            attempts += 1;
+            let attempt = attempts;
+            async move {
+                if attempt <= 2 {
+                    Err(network_error())
+                } else {
+                    Ok(vec![1, 2, 3])
+                }
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), [1, 2, 3]);
+        assert_eq!(attempts, 3);
+
+        let mut attempts = 0;
+        let result: Result<(), _> = retry_on_network_error(1, Duration::from_millis(1), || {
+            attempts += 1;
+            async { Err(network_error()) }
+        })
+        .await;
+        assert!(matches!(result, Err(RequestError::Network(_))));
+        assert_eq!(attempts, 2);
+    }
+
+    #[tokio::test]
+    async fn retried_download_keeps_only_the_last_attempt() {
+        use crate::test_utils::{dropped_connection, MockServer};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let downloads = AtomicUsize::new(0);
+        let server = MockServer::new(move |request| match request.method.as_str() {
+            "GetFile" => get_file_response("documents/file_1.txt"),
+            _ if downloads.fetch_add(1, Ordering::SeqCst) < 2 => dropped_connection(),
+            _ => serde_json::json!("contents"),
+        })
+        .await;
+
+        let file = serde_json::from_value(file_json("id")).unwrap();
+        let mut data = b"old".to_vec();
+        download_to_vec_retrying(&server.bot, &file, &mut data, 3, Duration::from_millis(1))
+            .await
+            .unwrap();
+        assert_eq!(data, b"\"contents\"");
+        assert_eq!(
+            server.methods(),
+            [
+                "GetFile",
+                "file_1.txt",
+                "GetFile",
+                "file_1.txt",
+                "GetFile",
+                "file_1.txt"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn doesnt_retry_other_errors() {
+        let mut attempts = 0;
+        let result: Result<(), _> = retry_on_network_error(3, Duration::from_millis(1), || {
+            attempts += 1;
+            async { Err(RequestError::Api(teloxide::ApiError::FileIdInvalid)) }
+        })
+        .await;
+        assert!(matches!(result, Err(RequestError::Api(_))));
+        assert_eq!(attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn buffered_downloads_keep_order() {
+        // The first "file" takes the longest, but must still come out first.
+        let download = |data: u8, delay: u64| async move {
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+            Ok(vec![data])
+        };
+        let downloads = [download(1, 30), download(2, 10), download(3, 1)];
+        let result = try_join_buffered(downloads, 2).await.unwrap();
+        assert_eq!(result, [[1], [2], [3]]);
+    }
+
+    #[tokio::test]
+    async fn buffered_downloads_stop_at_error() {
+        let mut started = 0;
+        let downloads = (0..5).map(|i| {
+            started += 1;
+            async move {
+                if i == 1 {
+                    Err(RequestError::Api(teloxide::ApiError::FileIdInvalid))
+                } else {
+                    Ok(vec![i])
+                }
+            }
+        });
+        let result = try_join_buffered(downloads, 2).await;
+        assert!(matches!(result, Err(RequestError::Api(_))));
+        assert!(started < 5);
+    }
+
+    #[tokio::test]
+    async fn collect_single_chunk_without_copying() {
+        let chunk = Bytes::from_static(b"hello");
+        let chunks = futures::stream::iter([Ok::<_, RequestError>(chunk.clone())]);
+        let collected = collect_bytes(chunks, 5).await.unwrap();
+        assert_eq!(collected.as_ptr(), chunk.as_ptr());
+
+        let chunks = ["hel", "lo", " world"].map(|x| Ok::<_, RequestError>(Bytes::from(x)));
+        let collected = collect_bytes(futures::stream::iter(chunks), 0)
+            .await
+            .unwrap();
+        assert_eq!(collected, "hello world");
+
+        let chunks = futures::stream::iter(Vec::<Result<Bytes, RequestError>>::new());
+        assert!(collect_bytes(chunks, 0).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn local_file_in_chunks() {
+        use std::io::Write;
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&[7; 10]).unwrap();
+
+        let chunks: Vec<Bytes> = local_file_stream(file.path().to_path_buf(), 4)
+            .try_collect()
+            .await
+            .unwrap();
+        let sizes: Vec<_> = chunks.iter().map(Bytes::len).collect();
+        assert_eq!(sizes, [4, 4, 2]);
+
+        let result: Result<Vec<Bytes>, _> =
+            local_file_stream(PathBuf::from("/this/does/not/exist"), 4)
+                .try_collect()
+                .await;
+        assert!(matches!(result, Err(RequestError::Io(_))));
+    }
+
+    #[tokio::test]
+    async fn read_ahead_is_bounded() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let produced = Arc::new(AtomicUsize::new(0));
+        let produced_clone = produced.clone();
+        let fast = futures::stream::iter(0..50).map(move |_| {
+            produced_clone.fetch_add(1024, Ordering::SeqCst);
+            Ok(Bytes::from(vec![0; 1024]))
+        });
+
+        let stream = read_ahead(fast, 4096);
+        let mut stream = std::pin::pin!(stream);
+        let mut consumed = 0;
+        while let Some(chunk) = stream.next().await {
+            consumed += chunk.unwrap().len();
+            tokio::time::sleep(Duration::from_millis(2)).await;
+            // What's buffered, plus a chunk waiting for room.
+            assert!(produced.load(Ordering::SeqCst) - consumed <= 4096 + 1024);
+        }
+        assert_eq!(consumed, 50 * 1024);
+    }
+
+    #[tokio::test]
+    async fn read_ahead_passes_big_chunks_and_errors() {
+        let chunks = futures::stream::iter([Ok(Bytes::from(vec![1; 100])), Err(network_error())]);
+        let stream = read_ahead(chunks, 10);
+        let mut stream = std::pin::pin!(stream);
+        assert_eq!(stream.next().await.unwrap().unwrap().len(), 100);
+        assert!(stream.next().await.unwrap().is_err());
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn slow_download_times_out() {
+        let slow_chunks = futures::stream::iter(0..10).then(|i| async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            Ok::<_, RequestError>(vec![i])
+        });
+        let result = with_timeout(Duration::from_millis(50), async {
+            let mut data = Vec::new();
+            write_stream_to(slow_chunks, &mut data).await?;
+            Ok(data)
+        })
+        .await;
+        assert!(matches!(result, Err(DownloadError::TimedOut { .. })));
+
+        let result = with_timeout(Duration::from_millis(50), async { Ok(vec![1]) }).await;
+        assert_eq!(result.unwrap(), [1]);
+    }
+
+    #[tokio::test]
+    async fn timed_out_download_leaves_vec_alone() {
+        use crate::test_utils::{slow_response, MockServer};
+
+        let server = MockServer::new(|request| match request.method.as_str() {
+            "GetFile" => get_file_response("documents/file_1.txt"),
+            _ => slow_response(serde_json::json!("contents"), Duration::from_millis(20)),
+        })
+        .await;
+
+        let file = serde_json::from_value(file_json("id")).unwrap();
+        let mut data = b"old".to_vec();
+        let result = server
+            .bot
+            .download_file_to_vec_timeout(&file, &mut data, Duration::from_millis(50))
+            .await;
+        assert!(matches!(result, Err(DownloadError::TimedOut { .. })));
+        assert_eq!(data, b"old");
+
+        server
+            .bot
+            .download_file_to_vec_timeout(&file, &mut data, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(data, b"old\"contents\"");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn stuck_local_read_times_out() {
+        use crate::test_utils::MockServer;
+
+        // Reading a named pipe blocks until something writes to it.
+        let dir = tempfile::tempdir().unwrap();
+        let pipe = dir.path().join("pipe");
+        let status = std::process::Command::new("mkfifo")
+            .arg(&pipe)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let pipe_path = pipe.to_str().unwrap().to_string();
+        let server = MockServer::new(move |_| get_file_response(&pipe_path)).await;
+
+        let file = serde_json::from_value(file_json("id")).unwrap();
+        let mut data = Vec::new();
+        let result = server
+            .bot
+            .download_file_to_vec_timeout(&file, &mut data, Duration::from_millis(50))
+            .await;
+        assert!(matches!(result, Err(DownloadError::TimedOut { .. })));
+        assert!(data.is_empty());
+
+        // Unblock the abandoned read, so the runtime can shut down.
+        drop(std::fs::OpenOptions::new().write(true).open(&pipe).unwrap());
+    }
+
+    #[test]
+    fn file_extensions() {
+        let file = |path: &str| -> File {
+            serde_json::from_value(with_file("file", serde_json::json!({ "file_path": path })))
+                .unwrap()
+        };
+
+        let remote = file("photos/file_5.jpg");
+        assert_eq!(remote.extension(), Some("jpg"));
+        assert_eq!(remote.guess_mime(), Some(mime::IMAGE_JPEG));
+
+        let local = file("/var/lib/telegram-bot-api/token/videos/file_1.mp4");
+        assert_eq!(local.extension(), Some("mp4"));
+        assert_eq!(local.guess_mime().unwrap().essence_str(), "video/mp4");
+
+        let no_extension = file("documents/file_2");
+        assert_eq!(no_extension.extension(), None);
+        assert_eq!(no_extension.guess_mime(), None);
+    }
+
+    #[test]
+    fn local_paths() {
+        let file = |path: &str| -> File {
+            serde_json::from_value(with_file("file", serde_json::json!({ "file_path": path })))
+                .unwrap()
+        };
+
+        let empty = file("");
+        assert!(!empty.is_local());
+        assert!(!empty.local_path_exists());
+
+        let remote = file("photos/file_5.jpg");
+        assert!(!remote.is_local());
+        assert!(!remote.local_path_exists());
+
+        let missing = file("/this/does/not/exist.jpg");
+        assert!(missing.is_local());
+        assert!(!missing.local_path_exists());
+
+        let tempfile = tempfile::NamedTempFile::new().unwrap();
+        let present = file(tempfile.path().to_str().unwrap());
+        assert!(present.is_local());
+        assert!(present.local_path_exists());
+    }
+
+    #[test]
+    fn paths_within_base() {
+        let file = |path: &Path| -> File {
+            serde_json::from_value(with_file(
+                "file",
+                serde_json::json!({ "file_path": path.to_str().unwrap() }),
+            ))
+            .unwrap()
+        };
+
+        let base = tempfile::tempdir().unwrap();
+        std::fs::create_dir(base.path().join("photos")).unwrap();
+        std::fs::write(base.path().join("photos/file_1.jpg"), [1]).unwrap();
+        let outside = tempfile::NamedTempFile::new().unwrap();
+
+        assert!(file(&base.path().join("photos/file_1.jpg")).is_within(base.path()));
+        assert!(file(&base.path().join("photos/../photos/file_1.jpg")).is_within(base.path()));
+        assert!(!file(outside.path()).is_within(base.path()));
+
+        // Getting out with `..` doesn't work.
+        let name = outside.path().file_name().unwrap();
+        let escape = base.path().join("photos/../..").join(name);
+        assert!(!file(&escape).is_within(base.path()));
+
+        assert!(!file(&base.path().join("photos/missing.jpg")).is_within(base.path()));
+        assert!(!file(Path::new("photos/file_1.jpg")).is_within(base.path()));
+    }
+
+    #[test]
+    fn file_size_through_reply() {
+        let video = with_file(
+            "video",
+            serde_json::json!({
+                "width": 1280, "height": 720, "duration": 15, "mime_type": "video/mp4",
+            }),
+        );
+        let direct = message_with(serde_json::json!({ "video": video.clone() }));
+        let reply = message_with(serde_json::json!({
+            "message_id": 2,
+            "text": "look",
+            "reply_to_message": serde_json::to_value(&direct).unwrap(),
+        }));
+
+        for message in [&direct, &reply] {
+            let media = message.get_media_info().unwrap();
+            assert_eq!(media.file_size(), 1000);
+            assert!(media.exceeds(999));
+            assert!(!media.exceeds(1000));
+        }
+    }
+
+    #[test]
+    fn downloaded_media_dimensions() {
+        let file: File = serde_json::from_value(with_file(
+            "document",
+            serde_json::json!({ "file_path": "documents/file_0.png" }),
+        ))
+        .unwrap();
+        let message = message_with(serde_json::json!({
+            "document": with_file("document", serde_json::json!({
+                "thumb": with_file("thumb", serde_json::json!({ "width": 320, "height": 180 })),
+            })),
+        }));
+        let media = message.get_media_info().unwrap();
+        assert_eq!(media.kind, MediaKind::Document);
+
+        // Not an image, so Telegram's dimensions are all there is.
+        let downloaded = DownloadedMedia::new(&media, &file, b"garbage".to_vec());
+        assert_eq!((downloaded.width, downloaded.height), (320, 180));
+        assert_eq!(downloaded.mime_type, Some(mime::IMAGE_PNG));
+
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend(1280u32.to_be_bytes());
+        png.extend(720u32.to_be_bytes());
+        let downloaded = DownloadedMedia::new(&media, &file, png.clone());
+        assert_eq!((downloaded.width, downloaded.height), (1280, 720));
+        assert_eq!(downloaded.kind, MediaKind::Document);
+        assert_eq!(downloaded.bytes, png);
+    }
+
+    #[test]
+    fn round_videos_and_sound() {
+        let media = |json: serde_json::Value| {
+            let message = message_with(json);
+            let media = message.get_media_info().unwrap();
+            (media.is_round_video(), media.has_sound())
+        };
+
+        let thumb = with_file("thumb", serde_json::json!({ "width": 240, "height": 240 }));
+        assert_eq!(
+            media(serde_json::json!({
+                "video_note": with_file("note", serde_json::json!({
+                    "length": 240, "duration": 5, "thumb": thumb,
+                })),
+            })),
+            (true, true)
+        );
+        assert_eq!(
+            media(serde_json::json!({
+                "voice": with_file("voice", serde_json::json!({ "duration": 5, "mime_type": "audio/ogg" })),
+            })),
+            (false, true)
+        );
+        assert_eq!(
+            media(serde_json::json!({
+                "video": with_file("video", serde_json::json!({
+                    "width": 1280, "height": 720, "duration": 15, "mime_type": "video/mp4",
+                })),
+            })),
+            (false, true)
+        );
+        assert_eq!(
+            media(serde_json::json!({
+                "animation": with_file("gif", serde_json::json!({
+                    "width": 320, "height": 240, "duration": 3, "mime_type": "video/mp4",
+                })),
+                "document": with_file("gif", serde_json::json!({ "mime_type": "video/mp4" })),
+            })),
+            (false, false)
+        );
+        assert_eq!(
+            media(serde_json::json!({
+                "photo": [with_file("photo", serde_json::json!({ "width": 90, "height": 90 }))],
+            })),
+            (false, false)
+        );
+    }
+
+    #[test]
+    fn unique_ids() {
+        let mut sticker = with_file(
+            "sticker",
+            serde_json::json!({
+                "width": 512, "height": 512, "type": "regular",
+                "is_animated": false, "is_video": false,
+            }),
+        );
+        sticker["file_unique_id"] = "AgADunique".into();
+        let message = message_with(serde_json::json!({ "sticker": sticker }));
+        let media = message.get_media_info().unwrap();
+        assert_eq!(media.unique_id(), "AgADunique");
+        assert_eq!(media.file.id, "sticker");
+    }
+
+    #[test]
+    fn media_info_from_standalone_media() {
+        let sticker: Sticker = serde_json::from_value(with_file(
+            "sticker",
+            serde_json::json!({
+                "width": 512, "height": 256, "type": "regular",
+                "is_animated": true, "is_video": false,
+            }),
+        ))
+        .unwrap();
+        let info = media_info_from_sticker(&sticker);
+        assert_eq!(info.kind, MediaKind::Sticker);
+        assert_eq!((info.width, info.height), (512, 256));
+        assert!(info.is_vector_sticker);
+        assert_eq!(info.file.id, "sticker");
+
+        let document: Document = serde_json::from_value(with_file(
+            "doc",
+            serde_json::json!({ "mime_type": "video/mp4" }),
+        ))
+        .unwrap();
+        let info = media_info_from_document(&document);
+        assert_eq!(info.kind, MediaKind::Document);
+        assert!(info.is_video);
+
+        let video_note: VideoNote = serde_json::from_value(with_file(
+            "round",
+            serde_json::json!({ "length": 240, "duration": 3 }),
+        ))
+        .unwrap();
+        assert!(media_info_from_video_note(&video_note).is_none());
+    }
+
+    #[test]
+    fn input_files() {
+        let message = message_with(serde_json::json!({
+            "photo": [with_file("photo", serde_json::json!({ "width": 90, "height": 90 }))],
+        }));
+        let info = message.get_media_info().unwrap();
+        // Files sent by ID are serialized as just the ID.
+        assert_eq!(
+            serde_json::to_value(info.to_input_file()).unwrap(),
+            serde_json::json!("photo")
+        );
+        let upload = serde_json::to_value(info.to_input_file_bytes(vec![1, 2, 3])).unwrap();
+        assert!(upload.as_str().unwrap().starts_with("attach://"));
+    }
+
+    #[test]
+    fn owned_media_info() {
+        let owned = {
+            let message = message_with(serde_json::json!({
+                "video": with_file("video", serde_json::json!({
+                    "width": 1280, "height": 720, "duration": 5, "mime_type": "video/mp4",
+                    "thumb": with_file("thumb", serde_json::json!({ "width": 320, "height": 180 })),
+                })),
+            }));
+            let media = message.get_media_info().unwrap();
+            assert!(format!("{media:?}").contains("is_video: true"));
+            media.to_owned()
+        };
+
+        assert_eq!(owned.file.id, "video");
+        assert_eq!(owned.thumb.as_ref().unwrap().id, "thumb");
+        assert_eq!(owned.kind, MediaKind::Video);
+        assert!(format!("{owned:?}").contains("is_video: true"));
+
+        let info = owned.as_info();
+        assert!(info.is_raster());
+        assert!(info.has_sound());
+        assert_eq!(info.duration, Some(5));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn owned_media_info_serde() {
+        let message = message_with(serde_json::json!({
+            "voice": with_file("voice", serde_json::json!({
+                "duration": 3, "mime_type": "audio/ogg",
+            })),
+        }));
+        let owned = message.get_media_info().unwrap().to_owned();
+
+        let json = serde_json::to_value(&owned).unwrap();
+        assert_eq!(json["mime_type"], "audio/ogg");
+        assert_eq!(json["file"]["file_unique_id"], "voice");
+
+        let back: OwnedMediaInfo = serde_json::from_value(json).unwrap();
+        assert_eq!(back.kind, MediaKind::Voice);
+        assert_eq!(back.mime_type, Some("audio/ogg".parse().unwrap()));
+        assert_eq!(back.file, owned.file);
+        assert!(back.as_info().is_voice_or_video_note);
+    }
+
+    #[test]
+    fn has_media_agrees_with_media_info() {
+        let thumb = with_file("thumb", serde_json::json!({ "width": 240, "height": 240 }));
+        let photo = with_file("photo", serde_json::json!({ "width": 90, "height": 90 }));
+        let messages = [
+            serde_json::json!({ "text": "no media" }),
+            serde_json::json!({ "photo": [photo] }),
+            serde_json::json!({ "sticker": with_file("sticker", serde_json::json!({
+                "width": 512, "height": 512, "type": "regular",
+                "is_animated": true, "is_video": false,
+            })) }),
+            serde_json::json!({ "video_note": with_file("note", serde_json::json!({
+                "length": 240, "duration": 5,
+            })) }),
+            serde_json::json!({ "video_note": with_file("note", serde_json::json!({
+                "length": 240, "duration": 5, "thumb": thumb,
+            })) }),
+            serde_json::json!({ "document": with_file("document", serde_json::json!({})) }),
+            serde_json::json!({
+                "text": "reply",
+                "reply_to_message": {
+                    "message_id": 1, "date": 0,
+                    "chat": { "id": -1001, "type": "supergroup", "title": "chat" },
+                    "photo": [photo],
+                },
+            }),
+        ];
+        let expected = [false, true, true, false, true, true, true];
+
+        for (message, expected) in messages.into_iter().zip(expected) {
+            let message = message_with(message);
+            assert_eq!(message.has_media(), expected, "{message:?}");
+            assert_eq!(message.get_media_info().is_some(), expected);
+        }
+    }
+
+    #[test]
+    fn editable_by_me() {
+        let sent_at = 1_700_000_000;
+        let now = DateTime::from_timestamp(sent_at, 0).unwrap();
+        let bot = serde_json::json!({ "id": 2, "is_bot": true, "first_name": "Bot" });
+        let text = message_with(serde_json::json!({ "date": sent_at, "from": bot, "text": "hi" }));
+
+        assert!(text.is_editable_by_me(now));
+        assert!(text.is_editable_by_me(now + chrono::Duration::hours(47)));
+        assert!(!text.is_editable_by_me(now + chrono::Duration::hours(48)));
+
+        let by_user = message_with(serde_json::json!({ "date": sent_at, "text": "hi" }));
+        assert!(!by_user.is_editable_by_me(now));
+
+        let dice = message_with(serde_json::json!({
+            "date": sent_at, "from": bot, "dice": { "emoji": "🎲", "value": 3 },
+        }));
+        assert!(!dice.is_editable_by_me(now));
+
+        let forwarded = message_with(serde_json::json!({
+            "date": sent_at, "from": bot, "text": "hi",
+            "forward_sender_name": "Someone", "forward_date": 0,
+        }));
+        assert!(!forwarded.is_editable_by_me(now));
+    }
+
+    #[test]
+    fn effective_dates() {
+        let message = message_with(serde_json::json!({ "date": 1000, "text": "hi" }));
+        assert!(!message.was_edited());
+        assert_eq!(message.effective_date().timestamp(), 1000);
+
+        let edited = message_with(serde_json::json!({
+            "date": 1000, "edit_date": 2000, "text": "hi, edited",
+        }));
+        assert!(edited.was_edited());
+        assert_eq!(edited.effective_date().timestamp(), 2000);
+    }
+
+    #[test]
+    fn spoilers() {
+        // "😀" is 2 UTF-16 code units long.
+        let text = "😀 secret here, 🙈 and there";
+        let entities = [
+            MessageEntity::spoiler(3, 6),
+            MessageEntity::bold(0, 2),
+            MessageEntity::spoiler(16, 2),
+        ];
+        assert_eq!(strip_spoilers(text, &entities), "😀  here,  and there");
+        assert_eq!(strip_spoilers(text, &entities[1..2]), text);
+
+        let message = message_with(serde_json::json!({
+            "text": text,
+            "entities": [{ "type": "spoiler", "offset": 3, "length": 6 }],
+        }));
+        assert!(message.has_spoiler());
+
+        let message = message_with(serde_json::json!({
+            "photo": [with_file("photo", serde_json::json!({ "width": 90, "height": 90 }))],
+            "has_media_spoiler": true,
+        }));
+        assert!(message.has_spoiler());
+
+        let message = message_with(serde_json::json!({
+            "text": text,
+            "entities": [{ "type": "bold", "offset": 3, "length": 6 }],
+        }));
+        assert!(!message.has_spoiler());
+    }
+
+    #[test]
+    fn content_kinds() {
+        let message = message_with(serde_json::json!({ "text": "hi" }));
+        assert!(matches!(
+            message.content_kind(),
+            MessageContentKind::Text("hi")
+        ));
+
+        let message = message_with(serde_json::json!({ "dice": { "emoji": "🎲", "value": 4 } }));
+        assert!(
+            matches!(message.content_kind(), MessageContentKind::Dice(dice) if dice.value == 4)
+        );
+
+        let message = message_with(serde_json::json!({
+            "poll": {
+                "id": "poll", "question": "?", "total_voter_count": 0,
+                "options": [{ "text": "yes", "voter_count": 0 }, { "text": "no", "voter_count": 0 }],
+                "is_closed": false, "is_anonymous": true, "type": "regular",
+                "allows_multiple_answers": false,
+            },
+        }));
+        assert!(
+            matches!(message.content_kind(), MessageContentKind::Poll(poll) if poll.question == "?")
+        );
+
+        let location = serde_json::json!({ "latitude": 1.0, "longitude": 2.0 });
+        let message = message_with(serde_json::json!({ "location": location }));
+        assert!(matches!(
+            message.content_kind(),
+            MessageContentKind::Location(_)
+        ));
+        let message = message_with(serde_json::json!({
+            "location": location,
+            "venue": { "location": location, "title": "place", "address": "street" },
+        }));
+        assert!(matches!(
+            message.content_kind(),
+            MessageContentKind::Venue(_)
+        ));
+
+        let message = message_with(serde_json::json!({
+            "photo": [with_file("photo", serde_json::json!({ "width": 90, "height": 90 }))],
+        }));
+        assert!(
+            matches!(message.content_kind(), MessageContentKind::Media(media) if media.is_image)
+        );
+
+        // Media in the replied to message doesn't count.
+        let message = message_with(serde_json::json!({
+            "text": "reply",
+            "reply_to_message": {
+                "message_id": 1, "date": 0,
+                "chat": { "id": -1001, "type": "supergroup", "title": "chat" },
+                "photo": [with_file("photo", serde_json::json!({ "width": 90, "height": 90 }))],
+            },
+        }));
+        assert!(matches!(
+            message.content_kind(),
+            MessageContentKind::Text("reply")
+        ));
+
+        let message = message_with(serde_json::json!({ "new_chat_title": "new title" }));
+        assert!(matches!(
+            message.content_kind(),
+            MessageContentKind::Service
+        ));
+    }
+
+    #[test]
+    fn media_histograms() {
+        let sticker = |is_animated: bool, is_video: bool| {
+            message_with(serde_json::json!({
+                "sticker": with_file("sticker", serde_json::json!({
+                    "width": 512, "height": 512, "type": "regular",
+                    "is_animated": is_animated, "is_video": is_video,
+                })),
+            }))
+        };
+        let photo = message_with(serde_json::json!({
+            "photo": [with_file("photo", serde_json::json!({ "width": 90, "height": 90 }))],
+        }));
+        let messages = [
+            message_with(serde_json::json!({ "text": "hi" })),
+            photo.clone(),
+            photo,
+            sticker(false, false),
+            sticker(true, false),
+            sticker(false, true),
+            sticker(false, true),
+            message_with(serde_json::json!({
+                "voice": with_file("voice", serde_json::json!({
+                    "duration": 3, "mime_type": "audio/ogg",
+                })),
+            })),
+            message_with(serde_json::json!({
+                "animation": with_file("gif", serde_json::json!({
+                    "width": 320, "height": 240, "duration": 3, "mime_type": "video/mp4",
+                })),
+                "document": with_file("gif", serde_json::json!({ "mime_type": "video/mp4" })),
+            })),
+            message_with(serde_json::json!({ "dice": { "emoji": "🎲", "value": 4 } })),
+            message_with(serde_json::json!({ "new_chat_title": "new title" })),
+        ];
+
+        assert_eq!(
+            media_histogram(&messages),
+            MediaHistogram {
+                texts: 1,
+                photos: 2,
+                static_stickers: 1,
+                animated_stickers: 1,
+                video_stickers: 2,
+                voices: 1,
+                animations: 1,
+                dice: 1,
+                service: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(media_histogram(&[]), MediaHistogram::default());
+    }
+
+    #[tokio::test]
+    async fn send_media_by_kind() {
+        use crate::test_utils::{ok, sent_message, MockServer};
+
+        let server = MockServer::new(|_| ok(sent_message(10))).await;
+        let chat = ChatId(1);
+
+        let sent = server
+            .bot
+            .send_media(
+                chat,
+                b"jpeg".to_vec(),
+                MediaKind::Photo,
+                Some("nice".into()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(sent.id, MessageId(10));
+        server
+            .bot
+            .send_media(chat, b"mp4".to_vec(), MediaKind::Video, None)
+            .await
+            .unwrap();
+        server
+            .bot
+            .send_media(
+                chat,
+                b"webp".to_vec(),
+                MediaKind::Sticker,
+                Some("ignored".into()),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(server.methods(), ["SendPhoto", "SendVideo", "SendSticker"]);
+        let requests = server.requests();
+        assert!(requests[0].body.contains("nice"));
+        assert!(!requests[1].body.contains("caption"));
+        assert!(!requests[2].body.contains("ignored"));
+    }
+
+    #[tokio::test]
+    async fn resend_media_by_file_id() {
+        use crate::test_utils::{ok, sent_message, MockServer};
+
+        let server = MockServer::new(|_| ok(sent_message(10))).await;
+        let chat = ChatId(1);
+
+        let message = message_with(serde_json::json!({
+            "sticker": with_file("video_sticker_id", serde_json::json!({
+                "width": 512, "height": 512, "type": "regular",
+                "is_animated": false, "is_video": true,
+            })),
+        }));
+        let media = message.get_media_info().unwrap();
+        server.bot.resend_media(chat, &media, None).await.unwrap();
+
+        let message = message_with(serde_json::json!({
+            "animation": with_file("gif_id", serde_json::json!({
+                "width": 320, "height": 240, "duration": 3, "mime_type": "video/mp4",
+            })),
+            "document": with_file("gif_id", serde_json::json!({ "mime_type": "video/mp4" })),
+        }));
+        let media = message.get_media_info().unwrap();
+        server
+            .bot
+            .resend_media(chat, &media, Some("again".into()))
+            .await
+            .unwrap();
+
+        assert_eq!(server.methods(), ["SendSticker", "SendAnimation"]);
+        let requests = server.requests();
+        assert!(requests[0].body.contains("video_sticker_id"));
+        assert!(requests[1].body.contains("gif_id"));
+        assert!(requests[1].body.contains("again"));
+    }
+
+    fn get_file_response(path: &str) -> serde_json::Value {
+        crate::test_utils::ok(serde_json::json!({
+            "file_id": "id",
+            "file_unique_id": "id",
+            "file_size": 3,
+            "file_path": path,
+        }))
+    }
+
+    #[tokio::test]
+    async fn download_remote_file_to_path() {
+        use crate::test_utils::MockServer;
+
+        let server = MockServer::new(|request| match request.method.as_str() {
+            "GetFile" => get_file_response("documents/file_1.txt"),
+            _ => serde_json::json!("contents"),
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("cached.txt");
+        std::fs::write(&dest, "old").unwrap();
+
+        let file = serde_json::from_value(file_json("id")).unwrap();
+        server
+            .bot
+            .download_file_to_path(&file, &dest)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&dest).unwrap(), "\"contents\"");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn download_local_file_to_path() {
+        use crate::test_utils::MockServer;
+
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source.webp");
+        std::fs::write(&source, [1, 2, 3]).unwrap();
+        let source_path = source.to_str().unwrap().to_string();
+        let server = MockServer::new(move |_| get_file_response(&source_path)).await;
+
+        let file = serde_json::from_value(file_json("id")).unwrap();
+        let dest = dir.path().join("dest.webp");
+        std::fs::write(&dest, "old").unwrap();
+        server
+            .bot
+            .download_file_to_path(&file, &dest)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), [1, 2, 3]);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+
+        // It's a copy, so the server's file is safe from changes to it.
+        std::fs::write(&dest, "new").unwrap();
+        assert_eq!(std::fs::read(&source).unwrap(), [1, 2, 3]);
+
+        let linked = dir.path().join("linked.webp");
+        std::fs::write(&linked, "old").unwrap();
+        server
+            .bot
+            .download_file_to_path_linked(&file, &linked)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&linked).unwrap(), [1, 2, 3]);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 3);
+        std::fs::write(&source, [4]).unwrap();
+        assert_eq!(std::fs::read(&linked).unwrap(), [4]);
+
+        let missing = dir.path().join("missing").join("dest.webp");
+        let Err(RequestError::Io(e)) = server.bot.download_file_to_path(&file, &missing).await
+        else {
+            panic!("Expected an IO error");
+        };
+        assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+        assert!(e.to_string().contains("missing"));
+        assert_eq!(server.methods(), ["GetFile", "GetFile"]);
+    }
+
+    #[tokio::test]
+    async fn download_remote_file_to_temp() {
+        use crate::test_utils::MockServer;
+
+        let contents = "x".repeat(100_000);
+        let response = serde_json::json!(contents);
+        let server = MockServer::new(move |request| match request.method.as_str() {
+            "GetFile" => get_file_response("videos/file_1.mp4"),
+            _ => response.clone(),
+        })
+        .await;
+
+        let file = serde_json::from_value(file_json("id")).unwrap();
+        let (path, tempfile) = server
+            .bot
+            .download_file_to_temp_or_directly(&file)
+            .await
+            .unwrap();
+        assert!(tempfile.is_some());
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            format!("\"{contents}\"")
+        );
+    }
+
+    #[tokio::test]
+    async fn reused_vec_isnt_sized_by_stale_size() {
+        use crate::test_utils::MockServer;
+        use std::io::Write;
+
+        let server = MockServer::new(|_| serde_json::json!(null)).await;
+        let mut local = tempfile::NamedTempFile::new().unwrap();
+        local.write_all(&[7; 10]).unwrap();
+        let file: File = serde_json::from_value(serde_json::json!({
+            "file_id": "id",
+            "file_unique_id": "id",
+            "file_size": 1_000_000,
+            "file_path": local.path(),
+        }))
+        .unwrap();
+
+        let mut data = Vec::new();
+        for _ in 0..2 {
+            server
+                .bot
+                .download_resolved_file_to_vec(&file, &mut data)
+                .await
+                .unwrap();
+        }
+        assert_eq!(data, [7; 20]);
+        assert!(data.capacity() < 1000);
+        assert!(server.methods().is_empty());
+    }
+
+    #[tokio::test]
+    async fn limited_downloads_arent_sized_by_stale_size() {
+        use crate::test_utils::{ok, MockServer};
+        use std::io::Write;
+
+        let mut local = tempfile::NamedTempFile::new().unwrap();
+        local.write_all(&[7; 10]).unwrap();
+        let path = local.path().to_str().unwrap().to_string();
+        let server = MockServer::new(move |_| {
+            ok(serde_json::json!({
+                "file_id": "id",
+                "file_unique_id": "id",
+                "file_size": 1_000_000,
+                "file_path": path,
+            }))
+        })
+        .await;
+        let file = serde_json::from_value(file_json("id")).unwrap();
+
+        let mut data = Vec::new();
+        server
+            .bot
+            .download_file_to_vec_with_limit(&file, &mut data, 2_000_000)
+            .await
+            .unwrap();
+        assert_eq!(data, [7; 10]);
+        assert!(data.capacity() < 1000);
+
+        let mut data = Vec::new();
+        server
+            .bot
+            .download_file_to_vec_with_progress(&file, &mut data, |_, _| {})
+            .await
+            .unwrap();
+        assert_eq!(data, [7; 10]);
+        assert!(data.capacity() < 1000);
+    }
+
+    #[tokio::test]
+    async fn resolve_media_paths() {
+        use crate::test_utils::MockServer;
+
+        let server = MockServer::new(|request| {
+            if request.body.contains("local") {
+                get_file_response("/var/lib/telegram-bot-api/photos/file_1.jpg")
+            } else {
+                get_file_response("photos/file_1.jpg")
+            }
+        })
+        .await;
+
+        let local = serde_json::from_value(file_json("local")).unwrap();
+        assert_eq!(
+            server.bot.resolve_media_path(&local).await.unwrap(),
+            Some(PathBuf::from("/var/lib/telegram-bot-api/photos/file_1.jpg"))
+        );
+        let remote = serde_json::from_value(file_json("remote")).unwrap();
+        assert_eq!(server.bot.resolve_media_path(&remote).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn download_albums() {
+        use crate::test_utils::MockServer;
+
+        let server = MockServer::new(|request| {
+            if request.method == "GetFile" {
+                let id = ["first", "second", "lonely"]
+                    .into_iter()
+                    .find(|id| request.body.contains(id))
+                    .unwrap();
+                get_file_response(&format!("photos/{id}.jpg"))
+            } else {
+                serde_json::json!(request.method.trim_end_matches(".jpg"))
+            }
+        })
+        .await;
+        let photo = |id: &str, group: Option<&str>| {
+            message_with(serde_json::json!({
+                "photo": [with_file(id, serde_json::json!({ "width": 90, "height": 90 }))],
+                "media_group_id": group,
+            }))
+        };
+        let messages = [
+            photo("lonely", None),
+            photo("first", Some("album")),
+            photo("second", Some("album")),
+        ];
+
+        let album = server.bot.download_album(&messages).await.unwrap();
+        let contents: Vec<_> = album.iter().map(|x| x.bytes.as_slice()).collect();
+        assert_eq!(contents, [b"\"first\"".as_slice(), b"\"second\""]);
+        assert!(album.iter().all(|x| x.kind == MediaKind::Photo));
+
+        let single = server.bot.download_album(&messages[..1]).await.unwrap();
+        assert_eq!(single.len(), 1);
+        assert_eq!(single[0].bytes, b"\"lonely\"");
+    }
+
+    #[tokio::test]
+    async fn bot_username_is_cached() {
+        use crate::test_utils::{ok, MockServer};
+
+        let server = MockServer::new(|_| {
+            ok(serde_json::json!({
+                "id": 1234,
+                "is_bot": true,
+                "first_name": "Bot",
+                "username": "cool_bot",
+                "can_join_groups": true,
+                "can_read_all_group_messages": false,
+                "supports_inline_queries": false,
+            }))
+        })
+        .await;
+
+        assert_eq!(server.bot.bot_username().await.unwrap(), "cool_bot");
+        assert_eq!(server.bot.bot_username().await.unwrap(), "cool_bot");
+        assert_eq!(server.methods(), ["GetMe"]);
+    }
+
+    #[tokio::test]
+    async fn local_and_remote_download_errors() {
+        use crate::test_utils::MockServer;
+
+        let server = MockServer::new(|request| {
+            if request.body.contains("local") {
+                get_file_response("/this/does/not/exist.jpg")
+            } else {
+                serde_json::json!({
+                    "ok": false, "error_code": 400, "description": "Bad Request: wrong file id",
+                })
+            }
+        })
+        .await;
+
+        let file = |id| serde_json::from_value(file_json(id)).unwrap();
+        let mut data = Vec::new();
+        let result = server
+            .bot
+            .download_file_to_vec_ext(&file("local"), &mut data)
+            .await;
+        assert!(matches!(
+            result,
+            Err(DownloadError::LocalRead(e)) if e.kind() == std::io::ErrorKind::NotFound
+        ));
+        let result = server
+            .bot
+            .download_file_to_vec_timeout(&file("local"), &mut data, Duration::from_secs(5))
+            .await;
+        assert!(matches!(result, Err(DownloadError::LocalRead(_))));
+        let result = server
+            .bot
+            .download_file_to_vec_ext(&file("remote"), &mut data)
+            .await;
+        assert!(matches!(
+            result,
+            Err(DownloadError::Request(RequestError::Api(_)))
+        ));
+
+        // It can be sent to other tasks and threads.
+        fn assert_send_sync<T: Send + Sync + 'static>() {}
+        assert_send_sync::<DownloadError>();
+    }
+
+    #[tokio::test]
+    async fn get_file_errors() {
+        use crate::test_utils::MockServer;
+
+        let server = MockServer::new(|request| {
+            let description = if request.body.contains("big") {
+                "Bad Request: file is too big"
+            } else if request.body.contains("wrong") {
+                "Bad Request: wrong file id"
+            } else {
+                "Bad Request: something else"
+            };
+            serde_json::json!({ "ok": false, "error_code": 400, "description": description })
+        })
+        .await;
+
+        let file = |id| serde_json::from_value(file_json(id)).unwrap();
+        assert!(matches!(
+            server.bot.try_get_file(&file("big")).await,
+            Err(GetFileError::TooLarge)
+        ));
+        assert!(matches!(
+            server.bot.try_get_file(&file("wrong")).await,
+            Err(GetFileError::NotFound)
+        ));
+        assert!(matches!(
+            server.bot.try_get_file(&file("other")).await,
+            Err(GetFileError::Other(RequestError::Api(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn send_long_messages() {
+        use crate::test_utils::{ok, sent_message, MockServer};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let server = MockServer::new(|_| ok(sent_message(1))).await;
+        let text = format!("<b>{}</b>", "word ".repeat(1000));
+        let sent = server
+            .bot
+            .send_long_message(ChatId(1), &text, Some(ParseMode::Html))
+            .await
+            .unwrap();
+        assert_eq!(sent.len(), 2);
+        let requests = server.requests();
+        assert!(requests.iter().all(|x| x.method == "SendMessage"));
+        assert!(requests
+            .iter()
+            .all(|x| x.body.contains("\"parse_mode\":\"HTML\"")));
+        // The bold text is closed and reopened at the split.
+        assert!(requests[0].body.contains("</b>"));
+        assert!(requests[1].body.contains("<b>"));
+
+        // Failing to send a part stops the rest.
+        let count = AtomicUsize::new(0);
+        let server = MockServer::new(move |_| {
+            if count.fetch_add(1, Ordering::SeqCst) == 1 {
+                serde_json::json!({ "ok": false, "error_code": 400, "description": "x" })
+            } else {
+                ok(sent_message(1))
+            }
+        })
+        .await;
+        let text = "word ".repeat(3000);
+        let result = server.bot.send_long_message(ChatId(1), &text, None).await;
+        assert!(result.is_err());
+        assert_eq!(server.methods().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn long_message_parts_are_spaced_out() {
+        use crate::test_utils::{ok, sent_message, MockServer};
+
+        let server = MockServer::new(|_| ok(sent_message(1))).await;
+        let text = "word ".repeat(2500);
+        let delay = Duration::from_millis(100);
+        let start = std::time::Instant::now();
+        let sent = server
+            .bot
+            .send_long_message_with_delay(ChatId(1), &text, None, delay)
+            .await
+            .unwrap();
+        assert_eq!(sent.len(), 4);
+        assert!(start.elapsed() >= delay * 3);
+    }
+
+    #[tokio::test]
+    async fn reply_long_messages_in_threads() {
+        use crate::test_utils::{ok, sent_message, MockServer};
+
+        let server = MockServer::new(|_| ok(sent_message(1))).await;
+        let supergroup = |is_forum: bool| {
+            serde_json::json!({
+                "id": -100, "type": "supergroup", "title": "Group", "is_forum": is_forum,
+            })
+        };
+        let in_topic = message_with(serde_json::json!({
+            "message_id": 5,
+            "chat": supergroup(true),
+            "message_thread_id": 4,
+            "is_topic_message": true,
+            "text": "hi",
+        }));
+        let in_reply_thread = message_with(serde_json::json!({
+            "message_id": 6,
+            "chat": supergroup(false),
+            "message_thread_id": 2,
+            "text": "hi",
+        }));
+
+        let text = "word ".repeat(1000);
+        let sent = server
+            .bot
+            .reply_long_message(&in_topic, &text)
+            .await
+            .unwrap();
+        assert_eq!(sent.len(), 2);
+        server
+            .bot
+            .reply_long_message(&in_reply_thread, "hello")
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        for request in &requests[..2] {
+            assert!(request.body.contains("\"chat_id\":-100"));
+            assert!(request.body.contains("\"message_thread_id\":4"));
+            assert!(request.body.contains("\"reply_to_message_id\":5"));
+        }
+        assert!(!requests[2].body.contains("message_thread_id"));
+        assert!(requests[2].body.contains("\"reply_to_message_id\":6"));
+    }
+
+    #[tokio::test]
+    async fn typing_while_future_runs() {
+        use crate::test_utils::{ok, MockServer};
+
+        let server = MockServer::new(|_| ok(serde_json::json!(true))).await;
+        let output = server
+            .bot
+            .with_typing(ChatId(1), async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                42
+            })
+            .await;
+        assert_eq!(output, 42);
+        assert_eq!(server.methods(), ["SendChatAction"]);
+        assert!(server.requests()[0].body.contains("typing"));
+
+        let bot = server.bot.clone();
+        let panicked =
+            tokio::spawn(
+                async move { bot.with_typing(ChatId(1), async { panic!("oh no") }).await },
+            )
+            .await;
+        assert!(panicked.unwrap_err().is_panic());
+    }
+
+    #[tokio::test]
+    async fn hash_media_contents() {
+        use crate::test_utils::MockServer;
+        use std::io::Write;
+
+        let mut local = tempfile::NamedTempFile::new().unwrap();
+        local.write_all(b"abc").unwrap();
+        let path = local.path().to_str().unwrap().to_string();
+        let server = MockServer::new(move |_| get_file_response(&path)).await;
+
+        let file = serde_json::from_value(file_json("id")).unwrap();
+        let digest = server.bot.hash_media(&file).await.unwrap();
+        let hex: String = digest.iter().map(|x| format!("{x:02x}")).collect();
+        assert_eq!(
+            hex,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[tokio::test]
+    async fn download_file_heads() {
+        use crate::test_utils::MockServer;
+        use std::io::Write;
+
+        let mut local = tempfile::NamedTempFile::new().unwrap();
+        local.write_all(&[7; 100]).unwrap();
+        let local_path = local.path().to_str().unwrap().to_string();
+        let server = MockServer::new(move |request| match request.method.as_str() {
+            "GetFile" if request.body.contains("local") => get_file_response(&local_path),
+            "GetFile" => get_file_response("documents/file_1.pdf"),
+            _ => serde_json::json!("x".repeat(100_000)),
+        })
+        .await;
+
+        let remote = serde_json::from_value(file_json("remote")).unwrap();
+        let head = server.bot.download_file_head(&remote, 10).await.unwrap();
+        assert_eq!(head, b"\"xxxxxxxxx");
+
+        let local = serde_json::from_value(file_json("local")).unwrap();
+        assert_eq!(
+            server.bot.download_file_head(&local, 10).await.unwrap(),
+            [7; 10]
+        );
+        assert_eq!(
+            server.bot.download_file_head(&local, 1000).await.unwrap(),
+            [7; 100]
+        );
+    }
+
+    #[tokio::test]
+    async fn sniff_file_content_type() {
+        use crate::test_utils::MockServer;
+        use std::io::Write;
+
+        let mut local = tempfile::NamedTempFile::new().unwrap();
+        local.write_all(b"%PDF-1.7\n").unwrap();
+        local.write_all(&[0; 1000]).unwrap();
+        let path = local.path().to_str().unwrap().to_string();
+        let server = MockServer::new(move |_| get_file_response(&path)).await;
+
+        let file = serde_json::from_value(file_json("id")).unwrap();
+        assert_eq!(
+            server.bot.sniff_content_type(&file).await.unwrap(),
+            Some("application/pdf")
+        );
     }
 }
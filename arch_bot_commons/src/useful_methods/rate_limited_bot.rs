@@ -0,0 +1,284 @@
+use std::{
+    collections::HashMap,
+    future::IntoFuture,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use teloxide::{
+    types::{ChatId, File, FileMeta},
+    Bot, RequestError,
+};
+
+use super::FileResolver;
+
+/// A [`Bot`] that spaces out messages it sends, so that it stays under
+/// Telegram's limits instead of getting "Too Many Requests" errors.
+/// Sends are limited both per chat and globally, each with a token bucket.
+/// When Telegram still asks to retry after some time, all sends are paused
+/// for that long, since the limits it enforces aren't just per chat.
+///
+/// Everything [`super::BotStuff`] sends goes through the limiter. Other
+/// requests can be throttled the same way with [`FileResolver::send_to_chat`]:
+///
+/// ```ignore
+/// let bot = RateLimitedBot::new(bot);
+/// bot.send_to_chat(chat, bot.bot().send_message(chat, "hi")).await?;
+/// ```
+///
+/// Requests sent straight through [`FileResolver::bot`] are not throttled.
+///
+/// Clones share the same limits.
+#[derive(Clone)]
+pub struct RateLimitedBot {
+    bot: Bot,
+    limiter: Arc<Limiter>,
+}
+
+impl RateLimitedBot {
+    /// Telegram allows bots to send about 30 messages per second overall.
+    pub const DEFAULT_GLOBAL_PER_SECOND: u32 = 30;
+    /// Telegram doesn't like more than a message per second in a single chat.
+    pub const DEFAULT_CHAT_PER_SECOND: u32 = 1;
+
+    /// Wrap a bot with the limits of [`RateLimitedBot::DEFAULT_GLOBAL_PER_SECOND`]
+    /// and [`RateLimitedBot::DEFAULT_CHAT_PER_SECOND`].
+    #[must_use]
+    pub fn new(bot: Bot) -> Self {
+        Self::with_limits(
+            bot,
+            Self::DEFAULT_GLOBAL_PER_SECOND,
+            Self::DEFAULT_CHAT_PER_SECOND,
+        )
+    }
+
+    /// Wrap a bot that sends at most `global_per_second` messages per second
+    /// overall, and at most `chat_per_second` to any single chat.
+    ///
+    /// # Panics
+    /// Panics if either of the limits is zero.
+    #[must_use]
+    pub fn with_limits(bot: Bot, global_per_second: u32, chat_per_second: u32) -> Self {
+        assert!(
+            global_per_second > 0 && chat_per_second > 0,
+            "Rate limits must be above zero"
+        );
+        RateLimitedBot {
+            bot,
+            limiter: Arc::new(Limiter::new(global_per_second, chat_per_second)),
+        }
+    }
+}
+
+impl FileResolver for RateLimitedBot {
+    fn bot(&self) -> &Bot {
+        &self.bot
+    }
+    async fn resolve_file(&self, file: &FileMeta) -> Result<File, RequestError> {
+        self.bot.resolve_file(file).await
+    }
+    async fn send_to_chat<R, T>(&self, chat: ChatId, request: R) -> Result<T, RequestError>
+    where
+        R: IntoFuture<Output = Result<T, RequestError>> + Send,
+        R::IntoFuture: Send,
+        T: Send,
+    {
+        self.limiter.acquire(chat).await;
+        let result = request.await;
+        if let Err(RequestError::RetryAfter(duration)) = &result {
+            self.limiter.pause(chat, *duration);
+        }
+        result
+    }
+}
+
+/// Lets through up to `per_second` sends each second, refilling gradually.
+struct Bucket {
+    per_second: f64,
+    tokens: f64,
+    updated: Instant,
+    paused_until: Option<Instant>,
+}
+
+impl Bucket {
+    fn new(per_second: u32, now: Instant) -> Self {
+        Bucket {
+            per_second: per_second.into(),
+            tokens: per_second.into(),
+            updated: now,
+            paused_until: None,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.per_second);
+        self.updated = now;
+    }
+
+    /// How long until a send can be let through.
+    fn wait_time(&self, now: Instant) -> Duration {
+        let paused = match self.paused_until {
+            Some(until) => until.saturating_duration_since(now),
+            None => Duration::ZERO,
+        };
+        let refill = if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / self.per_second)
+        };
+        paused.max(refill)
+    }
+
+    /// If it's full and not paused, it's the same as a new one.
+    fn is_idle(&self, now: Instant) -> bool {
+        self.tokens >= self.per_second && self.wait_time(now).is_zero()
+    }
+}
+
+struct Buckets {
+    global: Bucket,
+    chats: HashMap<ChatId, Bucket>,
+}
+
+struct Limiter {
+    chat_per_second: u32,
+    buckets: Mutex<Buckets>,
+}
+
+impl Limiter {
+    fn new(global_per_second: u32, chat_per_second: u32) -> Self {
+        Limiter {
+            chat_per_second,
+            buckets: Mutex::new(Buckets {
+                global: Bucket::new(global_per_second, Instant::now()),
+                chats: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Waits until a send to `chat` is allowed, and counts it.
+    async fn acquire(&self, chat: ChatId) {
+        loop {
+            let wait = {
+                let mut buckets = self.buckets.lock().expect("Rate limiter lock got poisoned");
+                let now = Instant::now();
+                let Buckets { global, chats } = &mut *buckets;
+
+                global.refill(now);
+                let chat_bucket = chats
+                    .entry(chat)
+                    .or_insert_with(|| Bucket::new(self.chat_per_second, now));
+                chat_bucket.refill(now);
+
+                let wait = global.wait_time(now).max(chat_bucket.wait_time(now));
+                if wait.is_zero() {
+                    global.tokens -= 1.0;
+                    chat_bucket.tokens -= 1.0;
+                    chat_bucket.paused_until = None;
+                    chats.retain(|_, bucket| {
+                        bucket.refill(now);
+                        !bucket.is_idle(now)
+                    });
+                    return;
+                }
+                wait
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Stops all sends for this long, after Telegram asked to wait when
+    /// sending to `chat`.
+    fn pause(&self, chat: ChatId, duration: Duration) {
+        let mut buckets = self.buckets.lock().expect("Rate limiter lock got poisoned");
+        let now = Instant::now();
+        let until = now + duration;
+        let Buckets { global, chats } = &mut *buckets;
+
+        global.tokens = 0.0;
+        // Another chat might have asked for a longer pause already.
+        global.paused_until = global.paused_until.max(Some(until));
+        let bucket = chats
+            .entry(chat)
+            .or_insert_with(|| Bucket::new(self.chat_per_second, now));
+        bucket.tokens = 0.0;
+        bucket.paused_until = Some(until);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{ok, sent_message, MockServer};
+    use crate::useful_methods::{BotStuff, MediaKind};
+
+    #[tokio::test]
+    async fn bursts_are_spaced_out() {
+        let server = MockServer::new(|_| ok(sent_message(1))).await;
+        let bot = RateLimitedBot::with_limits(server.bot.clone(), 100, 10);
+
+        let start = Instant::now();
+        for _ in 0..13 {
+            bot.send_media(ChatId(1), vec![1, 2, 3], MediaKind::Photo, None)
+                .await
+                .unwrap();
+        }
+        // A second's worth goes right away, the other 3 wait 100ms each.
+        assert!(start.elapsed() >= Duration::from_millis(290));
+        assert_eq!(server.methods().len(), 13);
+    }
+
+    #[tokio::test]
+    async fn chat_actions_are_limited_too() {
+        let server = MockServer::new(|_| ok(serde_json::json!(true))).await;
+        let bot = RateLimitedBot::with_limits(server.bot.clone(), 100, 10);
+
+        let start = Instant::now();
+        for _ in 0..10 {
+            bot.typing(ChatId(1)).await.unwrap();
+        }
+        let _guard = bot.typing_guard(ChatId(1));
+        bot.with_typing(ChatId(2), tokio::time::sleep(Duration::from_millis(20)))
+            .await;
+        // The guard in the same chat waits for its turn, unlike the other chat.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(server.methods().len(), 11);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(server.methods().len(), 12);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn chats_are_limited_separately() {
+        let limiter = Limiter::new(100, 1);
+        let start = Instant::now();
+        for chat in 0..5 {
+            limiter.acquire(ChatId(chat)).await;
+        }
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn global_limit_applies_to_all_chats() {
+        let limiter = Limiter::new(10, 10);
+        let start = Instant::now();
+        for chat in 0..12 {
+            limiter.acquire(ChatId(chat)).await;
+        }
+        assert!(start.elapsed() >= Duration::from_millis(190));
+    }
+
+    #[tokio::test]
+    async fn retry_after_pauses_all_chats() {
+        let limiter = Limiter::new(100, 100);
+        limiter.pause(ChatId(1), Duration::from_millis(100));
+        limiter.pause(ChatId(2), Duration::from_millis(50));
+
+        let start = Instant::now();
+        limiter.acquire(ChatId(3)).await;
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        limiter.acquire(ChatId(1)).await;
+        assert!(start.elapsed() < Duration::from_millis(150));
+    }
+}
@@ -2,11 +2,14 @@ use futures::Future;
 use teloxide::{
     payloads::SendMessageSetters,
     requests::Requester,
-    types::{Message, MessageId, Recipient},
+    types::{Message, MessageEntity, MessageId, Recipient},
     Bot, RequestError,
 };
 
-use crate::teloxide_retry;
+use crate::{
+    teloxide_retry,
+    text::{truncate_utf16, utf16_len},
+};
 
 pub trait BotArchSendMsg {
     /// Opinionated method to send a message, with HTML markup,
@@ -81,7 +84,11 @@ impl SplitTokenType {
             SplitTokenType::Char => 0,
         }
     }
-    /// Returns the size of the next token of this type.
+    /// Returns the size of the next token of this type, in UTF-16 code units.
+    fn next_token_units(self, text: &str) -> usize {
+        utf16_len(&text[..self.next_token_len(text)])
+    }
+    /// Returns the size of the next token of this type, in bytes.
     fn next_token_len(self, text: &str) -> usize {
         // It'd be cooler to just use str::split* functions, but they can
         // jump over an arbitrary amount of splitting tokens in a single
@@ -127,13 +134,17 @@ impl SplitTokenType {
 /// Splits by sections (separated by 3 newlines), then by paragraphs (2 newlines),
 /// then by lines (1 newline), then by word (ASCII whitespace), then character by character,
 /// until it fits.
+///
+/// Text is only ever cut on `char` boundaries, so multi-byte characters
+/// stay whole even if that makes a token shorter than the max length.
 pub struct SplitOverLengthTokens<'a> {
     data: &'a str,
     max_len: usize,
 }
 
 impl SplitOverLengthTokens<'_> {
-    /// Create a new splitter with specified max length by bytes.
+    /// Create a new splitter with specified max length in UTF-16 code units,
+    /// which is how Telegram counts it.
     ///
     /// # Panics
     /// Panics if a max length of 4 or less is specified.
@@ -156,8 +167,9 @@ impl<'a> Iterator for SplitOverLengthTokens<'a> {
 
         let len = self.data.len();
 
-        // Early return if the whole string fits.
-        if len <= self.max_len {
+        // Early return if the whole string fits. It has at most as many
+        // UTF-16 code units as bytes, so only count them if it matters.
+        if len <= self.max_len || utf16_len(self.data) <= self.max_len {
             let output = self.data;
             self.data = &self.data[len..len];
             return Some(output);
@@ -166,13 +178,13 @@ impl<'a> Iterator for SplitOverLengthTokens<'a> {
         let mut split_type;
 
         split_type = SplitTokenType::Section;
-        if split_type.next_token_len(self.data) > self.max_len {
+        if split_type.next_token_units(self.data) > self.max_len {
             split_type = SplitTokenType::Paragraph;
-            if split_type.next_token_len(self.data) > self.max_len {
+            if split_type.next_token_units(self.data) > self.max_len {
                 split_type = SplitTokenType::Line;
-                if split_type.next_token_len(self.data) > self.max_len {
+                if split_type.next_token_units(self.data) > self.max_len {
                     split_type = SplitTokenType::Word;
-                    if split_type.next_token_len(self.data) > self.max_len {
+                    if split_type.next_token_units(self.data) > self.max_len {
                         split_type = SplitTokenType::Char;
                     }
                 }
@@ -184,20 +196,27 @@ impl<'a> Iterator for SplitOverLengthTokens<'a> {
 
         let preprocessed_data = self.data;
         let mut output_size = 0;
+        let mut output_units = 0;
         loop {
             let next_token_size = split_type.next_token_len(self.data);
-            let total_token_size = if output_size > 0 {
+            let next_token_units = split_type.next_token_units(self.data);
+            // Separators are ASCII, so they're as many units as bytes.
+            let (total_token_size, total_token_units) = if output_size > 0 {
                 // There is a separator between the previous and this token.
                 // Include its size.
-                next_token_size + split_type.jump_size()
+                (
+                    next_token_size + split_type.jump_size(),
+                    next_token_units + split_type.jump_size(),
+                )
             } else {
                 // This is the first token. Include as is.
-                next_token_size
+                (next_token_size, next_token_units)
             };
 
             // Can we fit it in?
-            if output_size + total_token_size <= self.max_len {
+            if output_units + total_token_units <= self.max_len {
                 output_size += total_token_size;
+                output_units += total_token_units;
                 self.data = &self.data[total_token_size..];
             } else {
                 break;
@@ -245,9 +264,655 @@ impl<'a> Iterator for SplitOverLengthTokens<'a> {
     }
 }
 
+/// Lazily split text into chunks of at most `max_len` UTF-16 code units, in the same
+/// way as [`Splitter::split`] does by default, borrowing them from the text.
+///
+/// # Panics
+/// Panics if a max length of less than 4 is specified, see [`SplitOverLengthTokens::new`].
+pub fn split_iter(text: &str, max_len: usize) -> impl Iterator<Item = &str> {
+    SplitOverLengthTokens::new(text, max_len)
+}
+
+/// A piece of marked up text that's never cut in the middle.
+#[derive(Clone, Copy, Debug)]
+enum MarkupAtom<'a> {
+    /// A character, an escape sequence, or something else that has to stay whole,
+    /// like a MarkdownV2 link.
+    Text(&'a str),
+    /// Formatting marker that starts a span, like `*`, ` ```rust\n ` or `<b>`.
+    Open { marker: &'a str, name: &'a str },
+    /// Formatting marker that ends the last span with this name.
+    Close { marker: &'a str, name: &'a str },
+}
+
+impl<'a> MarkupAtom<'a> {
+    fn as_str(self) -> &'a str {
+        match self {
+            MarkupAtom::Text(x)
+            | MarkupAtom::Open { marker: x, .. }
+            | MarkupAtom::Close { marker: x, .. } => x,
+        }
+    }
+
+    fn is_whitespace(self) -> bool {
+        matches!(self, MarkupAtom::Text(x) if x.chars().all(char::is_whitespace))
+    }
+}
+
+/// A markup language that text can be split in without breaking it.
+trait Markup {
+    /// Breaks marked up text into atoms.
+    fn atoms(text: &str) -> Vec<MarkupAtom<'_>>;
+    /// Returns the length of the marker that closes a span with this name.
+    fn closing_len(name: &str) -> usize;
+    /// Appends the marker that closes a span with this name.
+    fn push_closing(to: &mut String, name: &str);
+    /// Whether a span with this name is code, where whitespace matters.
+    fn is_code(name: &str) -> bool;
+}
+
+/// Telegram's `MarkdownV2`. Names of spans are their closing markers.
+struct MarkdownV2;
+
+impl MarkdownV2 {
+    /// Markers of formatting, longest first so that `__` isn't
+    /// mistaken for two `_`.
+    const MARKERS: [&'static str; 6] = ["||", "__", "*", "_", "~", "`"];
+
+    /// Returns the length of a `[text](url)` link at the start of `text`, if there is one.
+    fn link_len(text: &str) -> Option<usize> {
+        fn find_unescaped(text: &str, what: char) -> Option<usize> {
+            let mut escaped = false;
+            for (i, c) in text.char_indices() {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    c if c == what => return Some(i),
+                    _ => (),
+                }
+            }
+            None
+        }
+
+        let text_end = find_unescaped(text.strip_prefix('[')?, ']')? + 1;
+        let url = text[text_end + 1..].strip_prefix('(')?;
+        let url_end = find_unescaped(url, ')')?;
+        Some(text_end + 2 + url_end + 1)
+    }
+}
+
+impl Markup for MarkdownV2 {
+    fn atoms(text: &str) -> Vec<MarkupAtom<'_>> {
+        let mut atoms = Vec::new();
+        // Names of spans that are open at this point.
+        let mut open: Vec<&'static str> = Vec::new();
+        let mut rest = text;
+
+        while let Some(c) = rest.chars().next() {
+            let in_code = open.last().is_some_and(|x| Self::is_code(x));
+            let char_len = c.len_utf8();
+
+            let atom = if c == '\\' {
+                let escaped_len = rest[1..].chars().next().map_or(0, char::len_utf8);
+                MarkupAtom::Text(&rest[..1 + escaped_len])
+            } else if in_code {
+                // Only the closing marker is special inside code.
+                let name = open[open.len() - 1];
+                if rest.starts_with(name) {
+                    open.pop();
+                    MarkupAtom::Close { marker: name, name }
+                } else {
+                    MarkupAtom::Text(&rest[..char_len])
+                }
+            } else if let Some(code) = rest.strip_prefix("```") {
+                // The language of the code block is part of its opening marker.
+                let marker_len = match (code.find('\n'), code.find("```")) {
+                    (Some(newline), Some(end)) if newline < end => 3 + newline + 1,
+                    (Some(newline), None) => 3 + newline + 1,
+                    _ => 3,
+                };
+                open.push("```");
+                MarkupAtom::Open {
+                    marker: &rest[..marker_len],
+                    name: "```",
+                }
+            } else if let Some(len) = (c == '[').then(|| Self::link_len(rest)).flatten() {
+                MarkupAtom::Text(&rest[..len])
+            } else if let Some(marker) = Self::MARKERS.into_iter().find(|x| rest.starts_with(x)) {
+                if let Some(i) = open.iter().rposition(|x| *x == marker) {
+                    open.remove(i);
+                    MarkupAtom::Close {
+                        marker,
+                        name: marker,
+                    }
+                } else {
+                    open.push(marker);
+                    MarkupAtom::Open {
+                        marker,
+                        name: marker,
+                    }
+                }
+            } else {
+                MarkupAtom::Text(&rest[..char_len])
+            };
+
+            rest = &rest[atom.as_str().len()..];
+            atoms.push(atom);
+        }
+
+        atoms
+    }
+
+    fn closing_len(name: &str) -> usize {
+        name.len()
+    }
+
+    fn push_closing(to: &mut String, name: &str) {
+        to.push_str(name);
+    }
+
+    fn is_code(name: &str) -> bool {
+        matches!(name, "`" | "```")
+    }
+}
+
+/// Telegram's subset of HTML. Names of spans are their tag names.
+struct Html;
+
+impl Markup for Html {
+    fn atoms(text: &str) -> Vec<MarkupAtom<'_>> {
+        let mut atoms = Vec::new();
+        let mut rest = text;
+
+        while let Some(c) = rest.chars().next() {
+            let char_len = c.len_utf8();
+
+            // Both of these start with an ASCII character, so skipping
+            // a byte past it is fine, unlike for any other character.
+            let tag_len = (c == '<')
+                .then(|| rest[1..].find(['<', '>']))
+                .flatten()
+                .filter(|x| rest[1 + x..].starts_with('>'))
+                .map(|x| x + 2);
+            let entity_len = (c == '&')
+                .then(|| rest[1..].find(|x: char| !x.is_ascii_alphanumeric() && x != '#'))
+                .flatten()
+                .filter(|x| *x > 0 && rest[1 + x..].starts_with(';'))
+                .map(|x| x + 2);
+
+            let atom = if let Some(len) = tag_len {
+                let marker = &rest[..len];
+                let inside = &marker[1..len - 1];
+                match inside.strip_prefix('/') {
+                    Some(name) => MarkupAtom::Close {
+                        marker,
+                        name: name.trim(),
+                    },
+                    None => MarkupAtom::Open {
+                        marker,
+                        name: inside.split_whitespace().next().unwrap_or_default(),
+                    },
+                }
+            } else if let Some(len) = entity_len {
+                MarkupAtom::Text(&rest[..len])
+            } else {
+                MarkupAtom::Text(&rest[..char_len])
+            };
+
+            rest = &rest[atom.as_str().len()..];
+            atoms.push(atom);
+        }
+
+        atoms
+    }
+
+    fn closing_len(name: &str) -> usize {
+        "</>".len() + name.len()
+    }
+
+    fn push_closing(to: &mut String, name: &str) {
+        to.push_str("</");
+        to.push_str(name);
+        to.push('>');
+    }
+
+    fn is_code(name: &str) -> bool {
+        matches!(name, "code" | "pre")
+    }
+}
+
+/// Split marked up text into chunks of at most `max_len` UTF-16 code units,
+/// each of which is valid markup on its own.
+fn split_markup<M: Markup>(text: &str, max_len: usize) -> Vec<String> {
+    /// Opening markers of spans, with their names.
+    type Spans<'a> = Vec<(&'a str, &'a str)>;
+
+    fn apply<'a>(spans: &mut Spans<'a>, atom: MarkupAtom<'a>) {
+        match atom {
+            MarkupAtom::Text(_) => (),
+            MarkupAtom::Open { marker, name } => spans.push((marker, name)),
+            MarkupAtom::Close { name, .. } => {
+                if let Some(i) = spans.iter().rposition(|(_, x)| *x == name) {
+                    spans.remove(i);
+                }
+            }
+        }
+    }
+
+    let closing_len = |spans: &Spans| spans.iter().map(|(_, x)| M::closing_len(x)).sum::<usize>();
+
+    let atoms = M::atoms(text);
+    let mut chunks = Vec::new();
+    let mut open_spans: Spans = Vec::new();
+    let mut start = 0;
+
+    loop {
+        if open_spans.is_empty() {
+            while atoms.get(start).is_some_and(|x| x.is_whitespace()) {
+                start += 1;
+            }
+        }
+        if start >= atoms.len() {
+            break;
+        }
+
+        let mut len: usize = open_spans.iter().map(|(x, _)| utf16_len(x)).sum();
+        let mut spans = open_spans.clone();
+        let mut end = start;
+        // Places to split at, with spans open at that point.
+        let mut after_newline = None;
+        let mut after_space = None;
+        let mut after_text = None;
+
+        while let Some(&atom) = atoms.get(end) {
+            let mut new_spans = spans.clone();
+            apply(&mut new_spans, atom);
+            let atom_len = utf16_len(atom.as_str());
+            if len + atom_len + closing_len(&new_spans) > max_len {
+                break;
+            }
+            len += atom_len;
+            spans = new_spans;
+            end += 1;
+            match atom {
+                MarkupAtom::Text("\n") => after_newline = Some((end, spans.clone())),
+                x if x.is_whitespace() => after_space = Some((end, spans.clone())),
+                MarkupAtom::Text(_) => after_text = Some((end, spans.clone())),
+                _ => (),
+            }
+        }
+
+        if end < atoms.len() {
+            if let Some(split) = after_newline.or(after_space).or(after_text) {
+                (end, spans) = split;
+            } else {
+                // Not even a single atom fits. Put it in anyway.
+                while let Some(&atom) = atoms.get(end) {
+                    apply(&mut spans, atom);
+                    end += 1;
+                    if let MarkupAtom::Text(_) = atom {
+                        break;
+                    }
+                }
+            }
+        }
+
+        let mut content_end = end;
+        if !spans.last().is_some_and(|(_, x)| M::is_code(x)) {
+            while content_end > start && atoms[content_end - 1].is_whitespace() {
+                content_end -= 1;
+            }
+        }
+
+        let mut chunk = String::new();
+        for (marker, _) in &open_spans {
+            chunk.push_str(marker);
+        }
+        for atom in &atoms[start..content_end] {
+            chunk.push_str(atom.as_str());
+        }
+        for (_, name) in spans.iter().rev() {
+            M::push_closing(&mut chunk, name);
+        }
+        chunks.push(chunk);
+
+        open_spans = spans;
+        start = end;
+    }
+
+    chunks
+}
+
+/// Split text with `MarkdownV2` markup into chunks of at most `max_len` UTF-16 code units,
+/// each of which is valid markup on its own.
+///
+/// Links are never broken up. Formatting spans that are cut by a split are
+/// closed at the end of one chunk and opened again at the start of the next.
+/// Splits on newlines where possible, then on spaces, then anywhere else.
+///
+/// A link that's longer than `max_len` by itself gets a chunk of its own,
+/// which will be over the limit.
+#[must_use]
+pub fn split_markdown(text: &str, max_len: usize) -> Vec<String> {
+    Splitter::new().max_len(max_len).markdown(true).split(text)
+}
+
+/// Split text with HTML markup into chunks of at most `max_len` UTF-16 code units,
+/// each of which is valid markup on its own.
+///
+/// Tags and character entities like `&amp;` are never broken up. Tags that are
+/// still open at a split are closed at the end of one chunk and opened again,
+/// with the same attributes and in the same order, at the start of the next.
+/// Splits on newlines where possible, then on spaces, then anywhere else.
+#[must_use]
+pub fn split_html(text: &str, max_len: usize) -> Vec<String> {
+    Splitter::new().max_len(max_len).html(true).split(text)
+}
+
+/// Split text with entities into chunks of at most `max_len` UTF-16 code units, in the
+/// same way as [`SplitOverLengthTokens`], and give each chunk the entities
+/// that apply to it.
+///
+/// Entity offsets are counted in UTF-16 code units from the start of the chunk,
+/// like Telegram expects. An entity that spans over a split is split into two.
+///
+/// # Panics
+/// Panics if a max length of less than 4 is specified, see [`SplitOverLengthTokens::new`].
+#[must_use]
+pub fn split_with_entities(
+    text: &str,
+    entities: &[MessageEntity],
+    max_len: usize,
+) -> Vec<(String, Vec<MessageEntity>)> {
+    let mut output = Vec::new();
+    // the offset in MessageEntity is a count of UTF-16 code units
+    let mut utf16_offset = 0;
+    let mut byte_offset = 0;
+
+    for chunk in SplitOverLengthTokens::new(text, max_len) {
+        // The splitter borrows from the text, so this is where the chunk is in it.
+        let chunk_start = chunk.as_ptr() as usize - text.as_ptr() as usize;
+        utf16_offset += utf16_len(&text[byte_offset..chunk_start]);
+        byte_offset = chunk_start;
+
+        let start = utf16_offset;
+        let end = start + utf16_len(chunk);
+
+        let chunk_entities = entities
+            .iter()
+            .filter_map(|entity| {
+                let entity_start = entity.offset.max(start);
+                let entity_end = (entity.offset + entity.length).min(end);
+                (entity_start < entity_end).then(|| {
+                    let mut entity = entity.clone();
+                    entity.offset = entity_start - start;
+                    entity.length = entity_end - entity_start;
+                    entity
+                })
+            })
+            .collect();
+
+        output.push((chunk.to_string(), chunk_entities));
+    }
+
+    output
+}
+
+/// Split text into chunks of at most `max_len` UTF-16 code units, without cutting words.
+fn split_by_words(text: &str, max_len: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut rest = text.trim_start();
+
+    while !rest.is_empty() {
+        if utf16_len(rest) <= max_len {
+            chunks.push(rest.trim_end().to_string());
+            break;
+        }
+
+        let mut limit = truncate_utf16(rest, max_len).len();
+        if limit == 0 {
+            // Not even a single character fits. Put it in anyway.
+            limit = rest.chars().next().map_or(0, char::len_utf8);
+        }
+
+        // Whitespace right after the limit is fine to split at too.
+        let head = &rest[..limit];
+        let after = &rest[limit..];
+        let newline = match after.starts_with('\n') {
+            true => Some(limit),
+            false => head.rfind('\n'),
+        };
+        let whitespace = match after.starts_with(char::is_whitespace) {
+            true => Some(limit),
+            false => head.rfind(char::is_whitespace),
+        };
+
+        match newline.or(whitespace).filter(|x| *x > 0) {
+            Some(split) => {
+                chunks.push(rest[..split].trim_end().to_string());
+                rest = rest[split..].trim_start();
+            }
+            None => {
+                chunks.push(head.to_string());
+                rest = after.trim_start();
+            }
+        }
+    }
+
+    chunks
+}
+
+/// Split text into chunks of at most `max_len` UTF-16 code units, without cutting words.
+///
+/// Each chunk is cut at the last newline that fits, or at the last
+/// whitespace if there's no newline in it. A single word longer than
+/// `max_len` is cut wherever it has to be.
+#[must_use]
+pub fn split_on_words(text: &str, max_len: usize) -> Vec<String> {
+    Splitter::new()
+        .max_len(max_len)
+        .strategy(SplitStrategy::WordBoundary)
+        .split(text)
+}
+
+/// Split text into chunks that fit into a media caption, without cutting words.
+/// Like [`validate_caption`], it counts the length in UTF-16 code units.
+///
+/// The usual way to use this is to send the first chunk as the caption,
+/// and the rest as messages replying to the media:
+///
+/// ```ignore
+/// let mut chunks = split_caption(text).into_iter();
+/// let mut request = bot.send_photo(chat, photo);
+/// if let Some(caption) = chunks.next() {
+///     request = request.caption(caption);
+/// }
+/// let photo = request.await?;
+/// for chunk in chunks {
+///     bot.send_message(chat, chunk).reply_to_message_id(photo.id).await?;
+/// }
+/// ```
+///
+/// For marked up captions, use [`Splitter`] with [`Splitter::CAPTION_MAX_LEN`].
+#[must_use]
+pub fn split_caption(text: &str) -> Vec<String> {
+    split_on_words(text, Splitter::CAPTION_MAX_LEN)
+}
+
+/// Error returned by [`validate_caption`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CaptionTooLong {
+    /// Length of the caption, in UTF-16 code units.
+    pub len: usize,
+}
+
+impl std::fmt::Display for CaptionTooLong {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Caption is {} characters long, but at most {} are allowed",
+            self.len,
+            Splitter::CAPTION_MAX_LEN
+        )
+    }
+}
+
+impl std::error::Error for CaptionTooLong {}
+
+/// Error returned by [`validate_message_text`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextTooLong {
+    /// Length of the text, in UTF-16 code units.
+    pub len: usize,
+}
+
+impl std::fmt::Display for TextTooLong {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Message is {} characters long, but at most {} are allowed",
+            self.len,
+            Splitter::MESSAGE_MAX_LEN
+        )
+    }
+}
+
+impl std::error::Error for TextTooLong {}
+
+/// Check that `text` fits into a media caption, before Telegram rejects it.
+/// Its length is counted in UTF-16 code units, like Telegram does, so some
+/// emoji count as two. If it doesn't fit, [`split_caption`] can split it.
+///
+/// # Errors
+/// Errors if the caption is longer than [`Splitter::CAPTION_MAX_LEN`].
+pub fn validate_caption(text: &str) -> Result<(), CaptionTooLong> {
+    let len = utf16_len(text);
+    if len > Splitter::CAPTION_MAX_LEN {
+        return Err(CaptionTooLong { len });
+    }
+    Ok(())
+}
+
+/// Check that `text` fits into a single message, before Telegram rejects it.
+/// Its length is counted like in [`validate_caption`]. If it doesn't fit,
+/// [`Splitter`] can split it.
+///
+/// # Errors
+/// Errors if the text is longer than [`Splitter::MESSAGE_MAX_LEN`].
+pub fn validate_message_text(text: &str) -> Result<(), TextTooLong> {
+    let len = utf16_len(text);
+    if len > Splitter::MESSAGE_MAX_LEN {
+        return Err(TextTooLong { len });
+    }
+    Ok(())
+}
+
+/// Where [`Splitter`] prefers to split plain text.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SplitStrategy {
+    /// By sections, then paragraphs, then lines, then words, then characters,
+    /// like [`SplitOverLengthTokens`].
+    #[default]
+    Tokens,
+    /// At the last newline that fits, or else the last whitespace, like [`split_on_words`].
+    WordBoundary,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SplitMarkup {
+    MarkdownV2,
+    Html,
+}
+
+/// Configurable text splitter, for when the defaults of the `split_*`
+/// functions don't fit.
+#[derive(Clone, Copy, Debug)]
+#[must_use]
+pub struct Splitter {
+    max_len: usize,
+    strategy: SplitStrategy,
+    markup: Option<SplitMarkup>,
+}
+
+impl Default for Splitter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Splitter {
+    /// Maximum length of a message's text.
+    pub const MESSAGE_MAX_LEN: usize = 4096;
+    /// Maximum length of a media caption.
+    pub const CAPTION_MAX_LEN: usize = 1024;
+
+    /// Create a splitter for plain message text, that splits by
+    /// [`SplitStrategy::Tokens`] into chunks of [`Splitter::MESSAGE_MAX_LEN`] UTF-16 code units.
+    pub fn new() -> Self {
+        Splitter {
+            max_len: Self::MESSAGE_MAX_LEN,
+            strategy: SplitStrategy::Tokens,
+            markup: None,
+        }
+    }
+
+    /// Set the maximum length of a chunk, in UTF-16 code units, which is
+    /// how Telegram counts its limits.
+    pub fn max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len;
+        self
+    }
+
+    /// Set where to prefer splitting plain text. Doesn't matter for
+    /// marked up text, which is always split at newlines or spaces where possible.
+    pub fn strategy(mut self, strategy: SplitStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Set whether the text is `MarkdownV2`, like [`split_markdown`].
+    /// Overrides [`Splitter::html`].
+    pub fn markdown(mut self, markdown: bool) -> Self {
+        self.set_markup(SplitMarkup::MarkdownV2, markdown);
+        self
+    }
+
+    /// Set whether the text is HTML, like [`split_html`].
+    /// Overrides [`Splitter::markdown`].
+    pub fn html(mut self, html: bool) -> Self {
+        self.set_markup(SplitMarkup::Html, html);
+        self
+    }
+
+    fn set_markup(&mut self, markup: SplitMarkup, enabled: bool) {
+        if enabled {
+            self.markup = Some(markup);
+        } else if self.markup == Some(markup) {
+            self.markup = None;
+        }
+    }
+
+    /// Split the text into chunks.
+    ///
+    /// # Panics
+    /// Panics if a max length of less than 4 is set with [`SplitStrategy::Tokens`]
+    /// on plain text, see [`SplitOverLengthTokens::new`].
+    #[must_use]
+    pub fn split(&self, text: &str) -> Vec<String> {
+        match (self.markup, self.strategy) {
+            (Some(SplitMarkup::MarkdownV2), _) => split_markup::<MarkdownV2>(text, self.max_len),
+            (Some(SplitMarkup::Html), _) => split_markup::<Html>(text, self.max_len),
+            (None, SplitStrategy::Tokens) => {
+                split_iter(text, self.max_len).map(String::from).collect()
+            }
+            (None, SplitStrategy::WordBoundary) => split_by_words(text, self.max_len),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::SplitOverLengthTokens;
+    use super::*;
 
     #[test]
     fn word_split() {
@@ -287,4 +952,317 @@ mod tests {
         assert_eq!(splitter.next(), Some("7"));
         assert_eq!(splitter.next(), None);
     }
+
+    #[test]
+    fn multibyte_chars_straddling_max_len() {
+        // Each emoji is 2 UTF-16 code units, so the first one would end past the limit.
+        let data = "aaa😀😀";
+        let mut splitter = SplitOverLengthTokens::new(data, 4);
+        assert_eq!(splitter.next(), Some("aaa"));
+        assert_eq!(splitter.next(), Some("😀😀"));
+        assert_eq!(splitter.next(), None);
+
+        // Each CJK character is a single code unit, even though it's 3 bytes.
+        let data = "日本語テキスト";
+        let splitter = SplitOverLengthTokens::new(data, 4);
+        assert_eq!(splitter.collect::<Vec<_>>(), ["日本語テ", "キスト"]);
+
+        let data = "😀 日本語 é😀é 😀😀😀";
+        for max_len in 4..data.len() {
+            let chunks: Vec<_> = SplitOverLengthTokens::new(data, max_len).collect();
+            assert!(chunks.iter().all(|x| utf16_len(x) <= max_len));
+            assert_eq!(chunks.concat().replace(' ', ""), data.replace(' ', ""));
+        }
+    }
+
+    #[test]
+    fn markdown_spans_are_reopened() {
+        let data = "*bold text here* plain";
+        assert_eq!(split_markdown(data, 12), ["*bold text*", "*here* plain"]);
+
+        let data = "```rust\nlet a = 1;\nlet b = 2;\n```";
+        assert_eq!(
+            split_markdown(data, 24),
+            ["```rust\nlet a = 1;\n```", "```rust\nlet b = 2;\n```"]
+        );
+    }
+
+    #[test]
+    fn markdown_links_stay_whole() {
+        let paragraph = "Some *bold words* and a [link to a page](https://example.com/page\\)) \
+            with _italic *and bold* text_ after it\\. Then [another one](https://example.org) \
+            and ~struck *through*~ text\\.\n";
+        let data = paragraph.repeat(20);
+
+        for max_len in [60, 100, 250, 4096] {
+            let chunks = split_markdown(&data, max_len);
+            assert!(chunks.iter().all(|x| x.len() <= max_len));
+            for chunk in &chunks {
+                // Every link that got into the chunk is whole.
+                for (i, _) in chunk.match_indices('[') {
+                    let link = &chunk[i..];
+                    assert!(
+                        link.starts_with("[link to a page](https://example.com/page\\))")
+                            || link.starts_with("[another one](https://example.org)"),
+                        "{chunk}"
+                    );
+                }
+                // Every span that got opened is closed.
+                let atoms = MarkdownV2::atoms(chunk);
+                let opened = atoms
+                    .iter()
+                    .filter(|x| matches!(x, MarkupAtom::Open { .. }))
+                    .count();
+                let closed = atoms
+                    .iter()
+                    .filter(|x| matches!(x, MarkupAtom::Close { .. }))
+                    .count();
+                assert_eq!(opened, closed, "{chunk}");
+            }
+            let links = chunks.iter().map(|x| x.matches("[").count()).sum::<usize>();
+            assert_eq!(links, 40);
+        }
+    }
+
+    #[test]
+    fn html_tags_are_reopened_in_order() {
+        let data = "<b>bold <i>italic words</i> &amp; more</b>";
+        let chunks = split_html(data, 28);
+        assert_eq!(
+            chunks,
+            [
+                "<b>bold <i>italic</i></b>",
+                "<b><i>words</i> &amp;</b>",
+                "<b>more</b>",
+            ]
+        );
+
+        let data = "<pre><code class=\"language-rust\">let a = 1;\nlet b = 2;</code></pre>";
+        assert_eq!(
+            split_html(data, 60),
+            [
+                "<pre><code class=\"language-rust\">let a = 1;\n</code></pre>",
+                "<pre><code class=\"language-rust\">let b = 2;</code></pre>",
+            ]
+        );
+    }
+
+    #[test]
+    fn html_with_multibyte_text() {
+        assert_eq!(split_html("日本 <b>語</b>", 100), ["日本 <b>語</b>"]);
+        assert_eq!(split_html("&日;", 100), ["&日;"]);
+
+        // "🦀" is 4 bytes, and the CJK characters 3 bytes each.
+        let data = "<b>日本語 🦀🦀 テキスト</b>";
+        let chunks = split_html(data, 14);
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|x| utf16_len(x) <= 14));
+        assert!(chunks
+            .iter()
+            .all(|x| x.starts_with("<b>") && x.ends_with("</b>")));
+        let text: String = chunks
+            .iter()
+            .map(|x| x.trim_start_matches("<b>").trim_end_matches("</b>"))
+            .collect();
+        assert_eq!(text.replace(' ', ""), "日本語🦀🦀テキスト");
+    }
+
+    #[test]
+    fn html_is_never_cut_inside_tags() {
+        let paragraph =
+            "Some <b>bold words</b> and a <a href=\"https://example.com/?a=1&amp;b=2\">\
+            link to <i>a page</i></a> with <s>struck <u>underlined</u> text</s> after it.\n";
+        let data = paragraph.repeat(20);
+
+        for max_len in [80, 100, 250, 4096] {
+            let chunks = split_html(&data, max_len);
+            assert!(chunks.iter().all(|x| x.len() <= max_len));
+            for chunk in &chunks {
+                let mut open = Vec::new();
+                for atom in Html::atoms(chunk) {
+                    match atom {
+                        MarkupAtom::Text(x) => assert!(!x.contains(['<', '>']), "{chunk}"),
+                        MarkupAtom::Open { name, .. } => open.push(name),
+                        MarkupAtom::Close { name, .. } => {
+                            assert_eq!(open.pop(), Some(name), "{chunk}")
+                        }
+                    }
+                }
+                assert!(open.is_empty(), "{chunk}");
+            }
+        }
+    }
+
+    #[test]
+    fn entities_are_split_with_text() {
+        // "😀" is 2 UTF-16 code units long.
+        let data = "aaaa 😀bbb cccc";
+        let entities = [
+            MessageEntity::italic(0, 4),
+            MessageEntity::bold(5, 8),
+            MessageEntity::code(13, 2),
+        ];
+        let chunks = split_with_entities(data, &entities, 9);
+        assert_eq!(
+            chunks,
+            [
+                ("aaaa".to_string(), vec![MessageEntity::italic(0, 4)]),
+                ("😀bbb".to_string(), vec![MessageEntity::bold(0, 5)]),
+                (
+                    "cccc".to_string(),
+                    vec![MessageEntity::bold(0, 2), MessageEntity::code(2, 2)]
+                ),
+            ]
+        );
+
+        let chunks = split_with_entities(data, &entities, 100);
+        assert_eq!(chunks, [(data.to_string(), entities.to_vec())]);
+    }
+
+    #[test]
+    fn words_are_not_cut() {
+        let data = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do \
+            eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim \
+            veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo \
+            consequat. Duis aute irure dolor in reprehenderit in voluptate velit esse cillum \
+            dolore eu fugiat nulla pariatur.";
+        let words: Vec<_> = data.split_whitespace().collect();
+
+        for max_len in [14, 30, 50, 100] {
+            let chunks = split_on_words(data, max_len);
+            assert!(chunks.iter().all(|x| x.len() <= max_len));
+            let chunk_words: Vec<_> = chunks.iter().flat_map(|x| x.split_whitespace()).collect();
+            assert_eq!(chunk_words, words);
+        }
+    }
+
+    #[test]
+    fn words_prefer_newlines_and_cut_long_words() {
+        let data = "first line\nsecond line here";
+        assert_eq!(split_on_words(data, 20), ["first line", "second line here"]);
+        // Newlines win over spaces anywhere in the chunk.
+        assert_eq!(
+            split_on_words("a\nsecond line here", 15),
+            ["a", "second line", "here"]
+        );
+        assert_eq!(
+            split_on_words("tiny 日本語日本語日本語", 4),
+            ["tiny", "日本語日", "本語日本", "語"]
+        );
+    }
+
+    #[test]
+    fn splitter_picks_the_configured_split() {
+        let data = "aa\n\n\nbb\ncc dd ee";
+        let splitter = Splitter::new().max_len(12);
+        assert_eq!(splitter.split(data), ["aa", "bb\ncc dd ee"]);
+        assert_eq!(
+            splitter.strategy(SplitStrategy::WordBoundary).split(data),
+            ["aa\n\n\nbb", "cc dd ee"]
+        );
+
+        let data = "*bold <b>words</b>* and more";
+        let splitter = Splitter::new().max_len(16);
+        assert_eq!(splitter.split(data), ["*bold", "<b>words</b>*", "and more"]);
+        assert_eq!(
+            splitter.markdown(true).split(data),
+            ["*bold*", "*<b>words</b>*", "and more"]
+        );
+        let data = "<b>bold *words*</b> and more";
+        assert_eq!(
+            splitter.markdown(true).html(true).split(data),
+            ["<b>bold</b>", "<b>*words*</b>", "and more"]
+        );
+        assert_eq!(
+            splitter.html(true).markdown(false).split(data),
+            splitter.html(true).split(data)
+        );
+        assert_eq!(
+            splitter.html(true).html(false).split(data),
+            splitter.split(data)
+        );
+
+        assert_eq!(Splitter::new().split(data), [data]);
+    }
+
+    #[test]
+    fn captions_fit_the_caption_limit() {
+        assert_eq!(split_caption("short caption"), ["short caption"]);
+        // Fits for Telegram, though it's well over 1024 bytes.
+        let data = "Съешь же ещё этих мягких французских булок ".repeat(16);
+        let data = data.trim_end();
+        assert!(data.len() > 1024);
+        assert!(validate_caption(data).is_ok());
+        assert_eq!(split_caption(data), [data]);
+
+        let data = "Ünïcödé wörds, 日本語 and emoji 😀 ".repeat(100);
+        let chunks = split_caption(&data);
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|x| utf16_len(x) <= 1024));
+    }
+
+    #[test]
+    fn limits_are_in_utf16_code_units() {
+        // 660 code units, but over 1100 bytes.
+        let data = "Привет, мир ".repeat(55);
+        let data = data.trim_end();
+        assert!(data.len() > 1000 && utf16_len(data) < 1000);
+
+        let splitter = Splitter::new().max_len(1000);
+        assert_eq!(splitter.split(data), [data]);
+        assert_eq!(
+            splitter.strategy(SplitStrategy::WordBoundary).split(data),
+            [data]
+        );
+        assert_eq!(splitter.html(true).split(data), [data]);
+        assert_eq!(splitter.markdown(true).split(data), [data]);
+        let bold = [MessageEntity::bold(0, 6)];
+        assert_eq!(
+            split_with_entities(data, &bold, 1000),
+            [(data.to_string(), bold.to_vec())]
+        );
+
+        // Emoji are two units each, so they do take up more of the limit.
+        let chunks = split_on_words(&"😀".repeat(10), 10);
+        assert_eq!(chunks, ["😀😀😀😀😀", "😀😀😀😀😀"]);
+    }
+
+    #[test]
+    fn split_iter_borrows_from_text() {
+        let data = "12345 12345\n12345\n12\n12\n1234567".repeat(10);
+        let text_range = data.as_bytes().as_ptr_range();
+        let mut chunks = split_iter(&data, 6).peekable();
+        assert_eq!(chunks.peek(), Some(&"12345"));
+        for chunk in chunks.by_ref().take(5) {
+            assert!(text_range.contains(&chunk.as_ptr()));
+        }
+        assert_eq!(
+            chunks.collect::<Vec<_>>(),
+            Splitter::new().max_len(6).split(&data)[5..]
+        );
+    }
+
+    #[test]
+    fn caption_and_text_validation() {
+        assert_eq!(validate_caption(&"a".repeat(1024)), Ok(()));
+        assert_eq!(
+            validate_caption(&"a".repeat(1025)),
+            Err(CaptionTooLong { len: 1025 })
+        );
+        // Astral plane emoji are two UTF-16 code units each.
+        assert_eq!(validate_caption(&"🦀".repeat(512)), Ok(()));
+        assert_eq!(
+            validate_caption(&"🦀".repeat(513)),
+            Err(CaptionTooLong { len: 1026 })
+        );
+        // But characters from the basic plane are one, even if they
+        // take up several bytes.
+        assert_eq!(validate_caption(&"ы".repeat(1024)), Ok(()));
+
+        assert_eq!(validate_message_text(&"🦀".repeat(2048)), Ok(()));
+        assert_eq!(
+            validate_message_text(&"🦀".repeat(2049)),
+            Err(TextTooLong { len: 4098 })
+        );
+    }
 }